@@ -1,20 +1,20 @@
-use serde::{Deserialize, Serialize};
-use websocket_rooms::{core::{RoomLogic, Networked, PlayerFields, RoomFields}, proc_macros::{Networked, PlayerFields, RoomFields}};
+use websocket_rooms::{core::{FixedStr, RoomLogic}, proc_macros::{Networked, PlayerFields, Room}, serde::{Deserialize, Serialize}};
 
 #[derive(Clone, Networked, PlayerFields, Copy, Serialize, Deserialize, Default, Debug)]
+#[serde(crate = "websocket_rooms::serde")]
 pub struct Player {
     #[name]
-    pub test: [u8; 20],
+    pub test: FixedStr<20>,
 
     #[disconnected]
     pub  disconnected: bool,
 
-    #[private] // This field should only be sent to the owner of the player, the macro should also enforce this is an Option since
-    // only the owner should be able to see this field
+    #[private] // This field is only sent to the owner of the player
     pub  cards: u8,
 }
 
-#[derive(Clone, RoomFields, Copy, Serialize, Deserialize, Networked, Default, Debug)]
+#[derive(Clone, Room, Copy, Serialize, Deserialize, Default, Debug)]
+#[serde(crate = "websocket_rooms::serde")]
 pub struct Room {
     #[players]
     pub players: [Option<Player>; 8],
@@ -24,11 +24,13 @@ pub struct Room {
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(crate = "websocket_rooms::serde")]
 pub enum ClientGameEvent {
     Test,
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(crate = "websocket_rooms::serde")]
 pub enum ServerGameEvent {
     Test,
 }
@@ -1,29 +1,96 @@
-use leptos::{logging::log, prelude::{signal, ReadSignal, Set, Write, WriteSignal}};
+use std::time::Duration;
+
+use leptos::{logging::log, prelude::{set_interval_with_handle, signal, Get, GetUntracked, IntervalHandle, ReadSignal, Set, Write, WriteSignal}};
 use serde::de::DeserializeOwned;
-use web_sys::{wasm_bindgen::{prelude::Closure, JsCast, JsValue}, ErrorEvent, MessageEvent, WebSocket};
+use web_sys::{js_sys::Date, wasm_bindgen::{prelude::Closure, JsCast, JsValue}, ErrorEvent, MessageEvent, WebSocket};
 
-use crate::{events::ServerMessage, Networked, RoomFields, RoomLogic};
+use crate::{events::ServerMessage, ClientEvent, JoinRejection, Networked, PlayerFields, RoomFields, RoomLogic, ServerEvent};
 
 type HandleEventFn<T> = fn(ServerMessage<T>) -> ();
 
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ConnectionStatus {
     Connected,
     Disconnected,
+    Rejected(JoinRejection), // The server wouldn't let us join, this stays set after the socket closes
 }
 
-pub struct RoomContext<T: RoomFields + Networked + RoomLogic> {
+pub struct RoomContext<T: RoomFields + Networked + RoomLogic + 'static> {
     ws: WebSocket,
+    websocket_url: String,
+    handle_event: HandleEventFn<T>,
+    ping_interval: IntervalHandle,
+    signals: Signals<T>,
     pub connection_status: ReadSignal<ConnectionStatus>,
+    pub desync_count: ReadSignal<u32>, // How many times the room has been found to be out of sync with the server
+    pub rtt: ReadSignal<Option<f64>>, // The round trip time of the last ping in milliseconds, None until the first pong
+    pub revision: ReadSignal<u64>, // The revision of the server's room that `room` is, 0 until the room has been joined
+    clock_offset: ReadSignal<f64>, // Added to the local clock to get the server's
     pub room: ReadSignal<T>,
     pub set_room: WriteSignal<T>,
 }
 
-impl <T: RoomFields + Networked + RoomLogic> RoomContext<T> {
+impl<T> RoomContext<T>
+where
+    T: RoomFields + RoomLogic + Networked + DeserializeOwned + Default + Send + Sync + 'static,
+{
     pub fn send(&self, event: T::ClientGameEvent) -> Result<(), JsValue> {
-        let event = bincode::serialize(&event).unwrap();
-        self.ws.send_with_u8_array(&event)?;
+        send_event::<T>(&self.ws, &ClientEvent::GameEvent(event))
+    }
+
+    // Sends the name to join the room with, the server keeps waiting for another one after a `NameRejected`
+    pub fn join(&self, name: <T::Player as PlayerFields>::Name) -> Result<(), JsValue> {
+        send_event::<T>(&self.ws, &ClientEvent::JoinRoom { name })
+    }
+
+    // The host actions, the server ignores them from anyone other than the host. The index is the player slot of the target
+    pub fn kick(&self, index: usize) -> Result<(), JsValue> {
+        send_event::<T>(&self.ws, &ClientEvent::KickPlayer { index })
+    }
+
+    pub fn ban(&self, index: usize) -> Result<(), JsValue> {
+        send_event::<T>(&self.ws, &ClientEvent::BanPlayer { index })
+    }
+
+    pub fn transfer_host(&self, index: usize) -> Result<(), JsValue> {
+        send_event::<T>(&self.ws, &ClientEvent::TransferHost { index })
+    }
+
+    // Replaces the socket with a new one to the same room, closing the old one if it is still open. The room is kept and
+    // its revision sent with the query, so if the server still has that revision for us it only sends what has changed,
+    // otherwise it sends the whole room
+    pub fn reconnect(&mut self) -> Result<(), JsValue> {
+        // The old socket's handlers would otherwise still update the room and connection status as it closes
+        self.ws.set_onmessage(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onopen(None);
+        self.ws.set_onclose(None);
+        self.ping_interval.clear();
+        let _ = self.ws.close();
+
+        let separator = if self.websocket_url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}revision={}", self.websocket_url, separator, self.revision.get_untracked());
+        (self.ws, self.ping_interval) = open_socket(&url, self.signals, self.handle_event)?;
         Ok(())
     }
+
+    // The server's clock in milliseconds since the unix epoch, for comparing against deadlines sent by the server.
+    // Tracks the clock offset, so it updates whenever a new estimate comes in
+    pub fn server_time(&self) -> f64 {
+        Date::now() + self.clock_offset.get()
+    }
+}
+
+fn send_event<T: RoomLogic + RoomFields>(ws: &WebSocket, event: &ClientEvent<T>) -> Result<(), JsValue> {
+    let event = bincode::serialize(event).unwrap();
+    ws.send_with_u8_array(&event)?;
+    Ok(())
+}
+
+fn send_ping<T: RoomLogic + RoomFields>(ws: &WebSocket, rtt: Option<f64>) -> Result<(), JsValue> {
+    send_event::<T>(ws, &ClientEvent::Ping { client_time: Date::now(), rtt: rtt.map(|rtt| rtt.round() as u32) })
 }
 
 pub fn create_room_context<T>(websocket_url: &str, handle_event: HandleEventFn<T>) -> Result<RoomContext<T>, JsValue>
@@ -32,15 +99,112 @@ where
 {
     let (room, set_room) = signal(T::default());
     let (connection_status, set_connection_status) = signal(ConnectionStatus::Disconnected);
+    let (desync_count, set_desync_count) = signal(0);
+    let (rtt, set_rtt) = signal(None);
+    let (revision, set_revision) = signal(0); // The client starts with the default room, which is revision 0 on the server
+    let (clock_offset, set_clock_offset) = signal(0.0);
+    let signals = Signals { set_connection_status, set_desync_count, rtt, set_rtt, revision, set_revision, set_clock_offset, set_room };
+    let (ws, ping_interval) = open_socket(websocket_url, signals, handle_event)?;
+
+    let room = RoomContext {
+        ws,
+        websocket_url: websocket_url.to_string(),
+        handle_event,
+        ping_interval,
+        signals,
+        connection_status,
+        desync_count,
+        rtt,
+        revision,
+        clock_offset,
+        room,
+        set_room,
+    };
+    Ok(room)
+}
+
+// The signals a socket updates. They belong to the context rather than the socket, so a new socket carries on from the
+// room the last one left behind
+struct Signals<T: 'static> {
+    set_connection_status: WriteSignal<ConnectionStatus>,
+    set_desync_count: WriteSignal<u32>,
+    rtt: ReadSignal<Option<f64>>,
+    set_rtt: WriteSignal<Option<f64>>,
+    revision: ReadSignal<u64>,
+    set_revision: WriteSignal<u64>,
+    set_clock_offset: WriteSignal<f64>,
+    set_room: WriteSignal<T>,
+}
+
+// Signals are Copy whatever they hold, which deriving would require of T as well
+impl<T> Clone for Signals<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Signals<T> {}
+
+fn open_socket<T>(websocket_url: &str, signals: Signals<T>, handle_event: HandleEventFn<T>) -> Result<(WebSocket, IntervalHandle), JsValue>
+where
+    T: RoomFields + RoomLogic + Networked + DeserializeOwned + Default + Send + Sync + 'static,
+{
+    let Signals { set_connection_status, set_desync_count, rtt, set_rtt, revision, set_revision, set_clock_offset, set_room } = signals;
     let ws = WebSocket::new(websocket_url)?;
     ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
 
+    let mut awaiting_snapshot = false;
+    let snapshot_ws = ws.clone();
     let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
         if let Ok(data) = e.data().dyn_into::<web_sys::js_sys::ArrayBuffer>() {
             let array = web_sys::js_sys::Uint8Array::new(&data);
             let vec = array.to_vec();
             let event = bincode::deserialize::<ServerMessage<T>>(&vec).unwrap();
-            set_room.write().update_from_optional(event.room);
+
+            match event.event {
+                ServerEvent::JoinRejected { reason } => {
+                    log!("Couldn't join the room: {:?}", reason);
+                    set_connection_status.set(ConnectionStatus::Rejected(reason));
+                }
+                ServerEvent::RoomJoined => {
+                    // Pings before joining are ignored, so get the first estimate straight away
+                    let _ = send_ping::<T>(&snapshot_ws, rtt.get_untracked());
+                }
+                ServerEvent::Pong { client_time, server_time } => {
+                    // Assume the pong took half the round trip to get here
+                    let now = Date::now();
+                    let round_trip = now - client_time;
+                    set_clock_offset.set(server_time as f64 + round_trip / 2.0 - now);
+                    set_rtt.set(Some(round_trip));
+                }
+                _ => {}
+            }
+
+            match event.base_revision {
+                None => {
+                    *set_room.write() = event.room.clone().map(T::from_optional).unwrap_or_default();
+                    set_revision.set(event.revision);
+                    awaiting_snapshot = false;
+                }
+                Some(base_revision) if base_revision == revision.get_untracked() && !awaiting_snapshot => {
+                    let mut room = set_room.write();
+                    room.update_from_optional(event.room.clone());
+                    set_revision.set(event.revision);
+
+                    if event.checksum.is_some_and(|checksum| checksum != room.checksum()) {
+                        log!("Room is out of sync at revision {}, requesting a snapshot", event.revision);
+                        *set_desync_count.write() += 1;
+                        awaiting_snapshot = send_event::<T>(&snapshot_ws, &ClientEvent::RequestSnapshot).is_ok();
+                    }
+                }
+                Some(_) => {
+                    // We've missed an update, so changes can't be applied until we have the whole room again
+                    if !awaiting_snapshot {
+                        log!("Missed an update at revision {}, requesting a snapshot", revision.get_untracked());
+                        awaiting_snapshot = send_event::<T>(&snapshot_ws, &ClientEvent::RequestSnapshot).is_ok();
+                    }
+                }
+            }
 
             (handle_event)(event);
         }
@@ -60,12 +224,22 @@ where
     ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
     onopen_callback.forget();
 
+    let ping_ws = ws.clone();
+    let ping_interval = set_interval_with_handle(move || {
+        if ping_ws.ready_state() == WebSocket::OPEN {
+            let _ = send_ping::<T>(&ping_ws, rtt.get_untracked());
+        }
+    }, PING_INTERVAL)?;
+
     let onclose_callback = Closure::<dyn FnMut()>::new(move || {
-        set_connection_status.set(ConnectionStatus::Disconnected);
+        ping_interval.clear();
+        let mut connection_status = set_connection_status.write();
+        if !matches!(*connection_status, ConnectionStatus::Rejected(_)) {
+            *connection_status = ConnectionStatus::Disconnected;
+        }
     });
     ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
     onclose_callback.forget();
 
-    let room = RoomContext { ws, connection_status, room, set_room };
-    Ok(room)
+    Ok((ws, ping_interval))
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{Networked, RoomFields, RoomLogic};
+use crate::{NameRejection, Networked, PlayerFields, RoomFields, RoomLogic};
 
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub enum ServerEvent<T> {
@@ -9,23 +9,60 @@ pub enum ServerEvent<T> {
     PlayerLeft,
     PlayerDisconnected,
     PlayerReconnected,
-    HostChanged,
+    HostChanged { host: usize }, // The index of the new host
+    PlayerKicked { index: usize }, // The player at the index was removed by the host, it is sent to them before their socket is closed
+    PlayerBanned { index: usize }, // The same as a kick, but they can't join the room again
+    RoomClosed { reason: RoomCloseReason }, // Sent to everyone connected just before the room is removed
+    Pong { client_time: f64, server_time: u64 }, // The answer to a ping, server_time is in milliseconds since the unix epoch
+    PingChanged { index: usize }, // The `#[ping]` field of the player at the index has changed
+    Resynced,
+    NameRejected(NameRejection), // The name sent with JoinRoom wasn't allowed, the client can send another
+    JoinRejected { reason: JoinRejection }, // The client couldn't join the room, the socket is closed after this is sent
     #[default]
     Unknown,
     GameEvent(T),
 }
 
+// Why a client couldn't join a room, unlike a rejected name these are final
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum JoinRejection {
+    InvalidQuery, // The id or code in the join query was the wrong length
+    RoomFull,
+    Timeout, // No allowed name was sent in time
+    NoName, // The connection ended before a name was sent
+    Banned,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum RoomCloseReason {
+    Idle, // No player has sent anything for longer than the room's idle timeout
+    Expired, // The room has been open for longer than its maximum lifetime
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ServerMessage<T: RoomLogic + RoomLogic + Networked> {
     pub event: ServerEvent<T::ServerGameEvent>,
+    pub revision: u64, // The revision of the room once this message has been applied
+    pub base_revision: Option<u64>, // The revision the changes were made from, None if the room is a full snapshot
+    pub checksum: Option<u64>, // Sent every few updates, the checksum of the room once this message has been applied
     pub room: Option<T::Optional>
 }
 
+// Generic over the room so the name sent when joining is the same type as the room's `#[name]` field
 #[derive(Serialize, Deserialize, Default)]
-pub enum ClientEvent<GameEvent: Serialize> {
-    JoinRoom { name: [u8; 20] },
+#[serde(bound = "")]
+pub enum ClientEvent<T: RoomLogic + RoomFields> {
+    JoinRoom { name: <T::Player as PlayerFields>::Name },
     LeaveRoom,
+    RequestSnapshot, // Sent when the client has missed an update and needs the whole room again
+    // Only allowed from the host, the index is the player slot of the target
+    KickPlayer { index: usize },
+    BanPlayer { index: usize },
+    TransferHost { index: usize },
+    // Sent every few seconds to measure the round trip time and the server's clock, client_time is `Date.now()` when it was sent.
+    // rtt is the round trip time of the last ping in milliseconds, for the player's `#[ping]` field
+    Ping { client_time: f64, rtt: Option<u32> },
     #[default]
     Unknown,
-    GameEvent(GameEvent),
+    GameEvent(T::ClientGameEvent),
 }
\ No newline at end of file
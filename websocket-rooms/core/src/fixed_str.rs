@@ -0,0 +1,174 @@
+use std::fmt;
+
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{checksum_bytes, Networked, CHECKSUM_SEED};
+
+// A UTF-8 string stored inline in at most N bytes, so it can be Copy like the rest of a room.
+// Anything longer is truncated to the last character that fits rather than splitting a character
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedStr<const N: usize> {
+    bytes: [u8; N], // Always valid UTF-8 up to len, and zeroed after it so equality only depends on the string
+    len: usize,
+}
+
+impl<const N: usize> FixedStr<N> {
+    pub fn new(value: &str) -> Self {
+        let mut len = value.len().min(N);
+        while !value.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut bytes = [0; N];
+        bytes[..len].copy_from_slice(&value.as_bytes()[..len]);
+        Self { bytes, len }
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<const N: usize> Default for FixedStr<N> {
+    fn default() -> Self {
+        Self { bytes: [0; N], len: 0 }
+    }
+}
+
+impl<const N: usize> From<&str> for FixedStr<N> {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<const N: usize> AsRef<str> for FixedStr<N> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> fmt::Debug for FixedStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+// Sent as a plain string, so only the used bytes go over the wire
+impl<const N: usize> Serialize for FixedStr<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+// Strings that are too long are truncated the same way as `new` instead of being rejected
+impl<'de, const N: usize> Deserialize<'de> for FixedStr<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FixedStrVisitor<const N: usize>;
+
+        impl<const N: usize> Visitor<'_> for FixedStrVisitor<N> {
+            type Value = FixedStr<N>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(FixedStr::new(value))
+            }
+        }
+
+        deserializer.deserialize_str(FixedStrVisitor::<N>)
+    }
+}
+
+impl<const N: usize> Networked for FixedStr<N> {
+    type Optional = Self;
+
+    fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+        if let Some(optional) = optional {
+            *self = optional;
+        }
+    }
+
+    fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+        if self != other {
+            Some(*other)
+        } else {
+            None
+        }
+    }
+
+    fn into_optional(&self) -> Option<Self::Optional> {
+        Some(*self)
+    }
+
+    fn from_optional(optional: Self::Optional) -> Self {
+        optional
+    }
+
+    // The same as String, so switching between the two doesn't change a room's checksum
+    fn checksum(&self) -> u64 {
+        checksum_bytes(CHECKSUM_SEED, self.as_str().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_keeps_short_strings() {
+        let value = FixedStr::<8>::new("abc");
+        assert_eq!(value.as_str(), "abc");
+        assert_eq!(value.len(), 3);
+        assert!(FixedStr::<8>::new("").is_empty());
+    }
+
+    #[test]
+    fn new_truncates_at_a_char_boundary() {
+        // "é" is two bytes, so only one of them would fit after "ab"
+        assert_eq!(FixedStr::<3>::new("abé").as_str(), "ab");
+        assert_eq!(FixedStr::<4>::new("abé").as_str(), "abé");
+        assert_eq!(FixedStr::<3>::new("🦀").as_str(), "");
+    }
+
+    #[test]
+    fn truncated_strings_equal_their_prefix() {
+        assert_eq!(FixedStr::<4>::new("abcdef"), FixedStr::<4>::new("abcd"));
+        assert_eq!(FixedStr::<4>::new("abcdef").checksum(), FixedStr::<4>::new("abcd").checksum());
+    }
+
+    #[test]
+    fn serializes_as_a_string() {
+        let value = FixedStr::<8>::new("abc");
+        let bytes = bincode::serialize(&value).unwrap();
+        assert_eq!(bytes, bincode::serialize("abc").unwrap());
+        assert_eq!(bincode::deserialize::<FixedStr<8>>(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn deserializing_over_long_strings_truncates() {
+        let bytes = bincode::serialize("abcdé").unwrap();
+        assert_eq!(bincode::deserialize::<FixedStr<5>>(&bytes).unwrap().as_str(), "abcd");
+        assert_eq!(bincode::deserialize::<FixedStr<2>>(&bytes).unwrap().as_str(), "ab");
+    }
+
+    #[test]
+    fn checksum_matches_string() {
+        assert_eq!(FixedStr::<8>::new("abc").checksum(), String::from("abc").checksum());
+    }
+}
@@ -1,22 +1,43 @@
+use std::time::Duration;
+
 use serde::{de::DeserializeOwned, Serialize};
 mod networked;
+mod fixed_str;
+mod names;
 mod events;
 mod server;
 mod client;
 
-pub use networked::Networked;
-pub use events::{ClientEvent, ServerEvent};
-pub use server::{ServerRoom, Rooms, RoomJoinQuery};
+pub use networked::{Networked, OptionOptional, VecOptional, MapOptional, EnumOptional, privatised_players, checksum_bytes, CHECKSUM_SEED};
+pub use fixed_str::FixedStr;
+pub use names::{NamePolicy, NameRejection, DuplicateNames};
+pub use events::{ClientEvent, ServerEvent, JoinRejection, RoomCloseReason};
+pub use server::{ServerRoom, Rooms, RoomJoinQuery, RoomOptions, HostMigration, ScheduleHandle};
+pub use client::{create_room_context, ConnectionStatus, RoomContext};
+
+// Used by the derives through `websocket_rooms`, so user crates always get the same versions the library was built with
+pub use serde;
+pub use bincode;
 
 pub trait PlayerFields {
-    fn name(&self) -> &[u8];
-    fn set_name(&mut self, name: &[u8]);
+    type Name: AsRef<str> + Serialize + DeserializeOwned + Send; // The type of the `#[name]` field, clients send one when joining
+
+    fn name(&self) -> &str;
+    fn set_name(&mut self, name: &str);
     fn disconnected(&self) -> bool;
     fn set_disconnected(&mut self, disconnected: bool);
+
+    // The round trip time to the player in milliseconds, only stored if the player has a `#[ping]` field
+    fn ping(&self) -> Option<u32> {
+        None
+    }
+
+    fn set_ping(&mut self, _ping: u32) {}
 }
 
 pub trait RoomFields {
     type Player: PlayerFields + Default;
+    const MAX_PLAYERS: usize; // The number of player slots, the length of `players`
 
     fn players(&self) -> &[Option<Self::Player>];
     fn players_mut(&mut self) -> &mut [Option<Self::Player>];
@@ -36,6 +57,32 @@ where
     // This is because the client should be able to predict the outcome of an action before the server sends the update
     fn validate_event(&self, player_index: usize, action: &Self::ClientGameEvent) -> bool;
 
+    // Returns the version of the room the player at `player_index` is allowed to see, for example with enemy units outside
+    // of their vision removed. Changes are calculated against the last view sent to each player, so a value that was hidden
+    // and is then revealed is still sent. By default every player can see the whole room
+    fn view_for(&self, _player_index: usize) -> Self
+    where
+        Self: Sized + Clone,
+    {
+        self.clone()
+    }
+
+    // Called at the room's tick interval with the time since the last tick, for games that change over time without
+    // anyone sending an event. Changes made here still have to be sent with `update_all` or one of the others
+    fn on_tick(_room: &mut ServerRoom<Self>, _dt: Duration)
+    where
+        Self: RoomFields + Sized,
+    {
+    }
+
+    // Called with an event passed to `ServerRoom::schedule` once it is due, for turn timers and countdowns.
+    // Like `on_tick`, any changes have to be sent by the hook
+    fn on_scheduled(_room: &mut ServerRoom<Self>, _event: Self::ServerGameEvent)
+    where
+        Self: RoomFields + Sized,
+    {
+    }
+
     // Ideally in the future theres some shared update function here that can be used by the client and server
     // so the client can be given instant feedback on their actions thanks in part to the validate_action function
     // fn handle_event(&mut self, player_index: usize, event: &ClientEvent<Self::ClientGameEvent>);
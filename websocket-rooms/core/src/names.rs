@@ -0,0 +1,221 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::PlayerFields;
+
+// Closures rather than function pointers, so they can capture a list or config loaded at runtime
+pub type AllowedCharsFn = Arc<dyn Fn(char) -> bool + Send + Sync>;
+pub type DenyFn = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DuplicateNames {
+    Suffix, // Add a number to the end of the name, "Sam" becomes "Sam (2)"
+    Reject,
+}
+
+// Why a name was rejected, sent back to the client so it can ask the player for a different one
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum NameRejection {
+    TooShort,
+    InvalidCharacter,
+    Denied,
+    Duplicate,
+}
+
+#[derive(Clone)]
+pub struct NamePolicy {
+    pub trim: bool, // Remove whitespace from the start and end of the name
+    pub min_length: usize, // In characters, after trimming
+    pub allowed_chars: AllowedCharsFn,
+    pub deny: Option<DenyFn>, // Return true to reject a name, for example one on a deny-list loaded at startup
+    pub duplicates: DuplicateNames,
+}
+
+impl Default for NamePolicy {
+    fn default() -> Self {
+        Self {
+            trim: true,
+            min_length: 1,
+            allowed_chars: Arc::new(|c| !c.is_control()),
+            deny: None,
+            duplicates: DuplicateNames::Suffix,
+        }
+    }
+}
+
+impl NamePolicy {
+    // Sets the player's name if the policy allows it. The checks are made against the name once it has been truncated to fit
+    // the player's name field, since that is what everyone else will see. `taken` is every other name in the room
+    pub fn apply<P: PlayerFields>(&self, player: &mut P, name: &str, taken: &[&str]) -> Result<(), NameRejection> {
+        let name = if self.trim { name.trim() } else { name };
+        if !name.chars().all(|c| (self.allowed_chars)(c)) {
+            return Err(NameRejection::InvalidCharacter);
+        }
+
+        player.set_name(name);
+        if self.trim {
+            let truncated = player.name().trim_end().to_string();
+            player.set_name(&truncated);
+        }
+
+        if player.name().chars().count() < self.min_length {
+            return Err(NameRejection::TooShort);
+        }
+
+        if self.deny.as_ref().is_some_and(|deny| deny(player.name())) {
+            return Err(NameRejection::Denied);
+        }
+
+        if !taken.contains(&player.name()) {
+            return Ok(());
+        }
+
+        if self.duplicates == DuplicateNames::Reject {
+            return Err(NameRejection::Duplicate);
+        }
+
+        // There are only so many other players, so one of the first few numbers has to be free
+        let name = player.name().to_string();
+        for number in 2..=taken.len() + 1 {
+            let suffix = format!(" ({})", number);
+
+            // Shorten the name until the suffix fits, if none of it is left the name is too short to tell apart
+            let mut base = name.as_str();
+            loop {
+                if base.is_empty() {
+                    return Err(NameRejection::Duplicate);
+                }
+
+                player.set_name(&format!("{}{}", base, suffix));
+                if player.name().ends_with(&suffix) {
+                    break;
+                }
+
+                let (index, _) = base.char_indices().last().unwrap_or_default();
+                base = &base[..index];
+            }
+
+            // The suffixed name has to pass the policy as well, otherwise the name can't be told apart
+            if !taken.contains(&player.name()) {
+                return if self.allows(player.name()) { Ok(()) } else { Err(NameRejection::Duplicate) };
+            }
+        }
+
+        Err(NameRejection::Duplicate)
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        name.chars().all(|c| (self.allowed_chars)(c)) && !self.deny.as_ref().is_some_and(|deny| deny(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FixedStr;
+
+    #[derive(Default)]
+    struct Player<const N: usize> {
+        name: FixedStr<N>,
+    }
+
+    impl<const N: usize> PlayerFields for Player<N> {
+        type Name = FixedStr<N>;
+
+        fn name(&self) -> &str {
+            self.name.as_str()
+        }
+
+        fn set_name(&mut self, name: &str) {
+            self.name = FixedStr::new(name);
+        }
+
+        fn disconnected(&self) -> bool {
+            false
+        }
+
+        fn set_disconnected(&mut self, _disconnected: bool) {}
+    }
+
+    fn apply<const N: usize>(policy: &NamePolicy, name: &str, taken: &[&str]) -> Result<String, NameRejection> {
+        let mut player = Player::<N>::default();
+        policy.apply(&mut player, name, taken).map(|()| player.name().to_string())
+    }
+
+    #[test]
+    fn trims_whitespace() {
+        assert_eq!(apply::<8>(&NamePolicy::default(), "  Sam ", &[]), Ok("Sam".to_string()));
+    }
+
+    #[test]
+    fn trims_again_after_truncating() {
+        // Cut to "Sam     " by the field, which would otherwise leave trailing spaces
+        assert_eq!(apply::<8>(&NamePolicy::default(), "Sam      Smith", &[]), Ok("Sam".to_string()));
+    }
+
+    #[test]
+    fn rejects_short_names_and_invalid_characters() {
+        assert_eq!(apply::<8>(&NamePolicy::default(), "   ", &[]), Err(NameRejection::TooShort));
+        assert_eq!(apply::<8>(&NamePolicy::default(), "Sa\nm", &[]), Err(NameRejection::InvalidCharacter));
+    }
+
+    #[test]
+    fn rejects_denied_names() {
+        let policy = NamePolicy { deny: Some(Arc::new(|name: &str| name.contains("admin"))), ..NamePolicy::default() };
+        assert_eq!(apply::<8>(&policy, "admin", &[]), Err(NameRejection::Denied));
+        assert_eq!(apply::<8>(&policy, "Sam", &[]), Ok("Sam".to_string()));
+    }
+
+    #[test]
+    fn deny_can_use_a_list_loaded_at_runtime() {
+        let denied: Vec<String> = "Sam,Alex".split(',').map(str::to_string).collect();
+        let policy = NamePolicy {
+            deny: Some(Arc::new(move |name: &str| denied.iter().any(|denied| denied.eq_ignore_ascii_case(name)))),
+            ..NamePolicy::default()
+        };
+        assert_eq!(apply::<8>(&policy, "sam", &[]), Err(NameRejection::Denied));
+        assert_eq!(apply::<8>(&policy, "Jo", &[]), Ok("Jo".to_string()));
+    }
+
+    #[test]
+    fn allowed_chars_can_capture() {
+        let allowed = "abc".to_string();
+        let policy = NamePolicy { allowed_chars: Arc::new(move |c| allowed.contains(c)), ..NamePolicy::default() };
+        assert_eq!(apply::<8>(&policy, "cab", &[]), Ok("cab".to_string()));
+        assert_eq!(apply::<8>(&policy, "Sam", &[]), Err(NameRejection::InvalidCharacter));
+    }
+
+    #[test]
+    fn suffixes_duplicates() {
+        let policy = NamePolicy::default();
+        assert_eq!(apply::<16>(&policy, "Sam", &["Sam"]), Ok("Sam (2)".to_string()));
+        assert_eq!(apply::<16>(&policy, "Sam", &["Sam", "Sam (2)"]), Ok("Sam (3)".to_string()));
+    }
+
+    #[test]
+    fn shortens_the_name_to_fit_the_suffix() {
+        assert_eq!(apply::<8>(&NamePolicy::default(), "Samantha", &["Samantha"]), Ok("Sama (2)".to_string()));
+    }
+
+    #[test]
+    fn rejects_duplicates_when_the_suffix_cannot_fit() {
+        assert_eq!(apply::<4>(&NamePolicy::default(), "Sam", &["Sam"]), Err(NameRejection::Duplicate));
+    }
+
+    #[test]
+    fn rejects_duplicates_when_the_suffix_is_not_allowed() {
+        let letters = NamePolicy { allowed_chars: Arc::new(|c| c.is_alphabetic()), ..NamePolicy::default() };
+        assert_eq!(apply::<16>(&letters, "Sam", &["Sam"]), Err(NameRejection::Duplicate));
+
+        let no_twos = NamePolicy { deny: Some(Arc::new(|name: &str| name.contains('2'))), ..NamePolicy::default() };
+        assert_eq!(apply::<16>(&no_twos, "Sam", &["Sam"]), Err(NameRejection::Duplicate));
+    }
+
+    #[test]
+    fn reject_mode_rejects_duplicates() {
+        let policy = NamePolicy { duplicates: DuplicateNames::Reject, ..NamePolicy::default() };
+        assert_eq!(apply::<8>(&policy, "Sam", &["Sam"]), Err(NameRejection::Duplicate));
+        assert_eq!(apply::<8>(&policy, "Sam", &["Alex"]), Ok("Sam".to_string()));
+    }
+}
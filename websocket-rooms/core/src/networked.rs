@@ -1,7 +1,9 @@
-use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::{BTreeMap, HashMap}, hash::Hash};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 pub trait Networked {
-    type Optional: Serialize + DeserializeOwned + Copy;
+    type Optional: Serialize + DeserializeOwned + Clone;
 
     fn update_from_optional(&mut self, optional: Option<Self::Optional>);
 
@@ -14,19 +16,53 @@ pub trait Networked {
 
     // Convert an optional into this
     fn from_optional(optional: Self::Optional) -> Self;
+
+    // A hash of everything that is networked, this is compared between the server and client so it must be stable across platforms
+    fn checksum(&self) -> u64;
+
+    // Removes anything from the optional that the player at `viewer` isn't allowed to see, `owned` is true when that
+    // player owns this value. Returns None if there is nothing left to send
+    fn privatised(optional: Self::Optional, _viewer: usize, _owned: bool) -> Option<Self::Optional> {
+        Some(optional)
+    }
+}
+
+pub const CHECKSUM_SEED: u64 = 0xcbf29ce484222325;
+
+// FNV-1a, continues the checksum with the given bytes
+pub fn checksum_bytes(checksum: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(checksum, |checksum, byte| (checksum ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Privatises an array of players, each player is owned by the viewer with the same index
+pub fn privatised_players<T, const N: usize>(optional: [Option<T::Optional>; N], viewer: usize) -> Option<[Option<T::Optional>; N]>
+where
+    T: Networked,
+{
+    let mut privatised: Option<[Option<T::Optional>; N]> = None;
+
+    for (index, optional) in optional.into_iter().enumerate() {
+        if let Some(optional) = optional {
+            if let Some(optional) = T::privatised(optional, viewer, index == viewer) {
+                privatised.get_or_insert_with(|| std::array::from_fn(|_| None))[index] = Some(optional);
+            }
+        }
+    }
+
+    privatised
 }
 
 impl<T, const N: usize> Networked for [T; N]
 where
-    T: Networked + Serialize + DeserializeOwned + Copy + Default,
+    T: Networked + Serialize + DeserializeOwned + Default,
     [Option<T::Optional>; N]: Serialize + DeserializeOwned
 {
     type Optional = [Option<T::Optional>; N];
 
     fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
         if let Some(optional) = optional {
-            for (this, optional) in self.iter_mut().zip(optional.iter()) {
-                this.update_from_optional(*optional);
+            for (this, optional) in self.iter_mut().zip(optional) {
+                this.update_from_optional(optional);
             }
         }
     }
@@ -36,7 +72,7 @@ where
     
         for (index, (x, y)) in self.iter().zip(other.iter()).enumerate() {
             if let Some(diff) = x.differences_with(y) {
-                optional.get_or_insert_with(|| [None; N])[index] = Some(diff);
+                optional.get_or_insert_with(|| std::array::from_fn(|_| None))[index] = Some(diff);
             }
         }
     
@@ -44,69 +80,394 @@ where
     }
 
     fn into_optional(&self) -> Option<Self::Optional> {
-        let mut optional = [None; N];
-        for (index, this) in self.iter().enumerate() {
-            optional[index] = this.into_optional();
-        }
-        Some(optional)
+        Some(std::array::from_fn(|index| self[index].into_optional()))
     }
 
     // This is the reason the array type must implement default
     fn from_optional(optional: Self::Optional) -> Self {
-        let mut this = [T::default(); N];
-        for (index, optional) in optional.iter().enumerate() {
+        let mut this: Self = std::array::from_fn(|_| T::default());
+        for (index, optional) in optional.into_iter().enumerate() {
             if let Some(optional) = optional {
-                this[index] = T::from_optional(*optional);
+                this[index] = T::from_optional(optional);
             }
         }
         this
     }
+
+    fn checksum(&self) -> u64 {
+        self.iter().fold(CHECKSUM_SEED, |checksum, this| checksum_bytes(checksum, &this.checksum().to_le_bytes()))
+    }
+
+    fn privatised(optional: Self::Optional, viewer: usize, owned: bool) -> Option<Self::Optional> {
+        let mut privatised: Option<Self::Optional> = None;
+
+        for (index, optional) in optional.into_iter().enumerate() {
+            if let Some(optional) = optional {
+                if let Some(optional) = T::privatised(optional, viewer, owned) {
+                    privatised.get_or_insert_with(|| std::array::from_fn(|_| None))[index] = Some(optional);
+                }
+            }
+        }
+
+        privatised
+    }
+}
+
+// The changes to an Option. An inserted value is kept apart from an update so it is still sent when none of it is visible
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub enum OptionOptional<T> {
+    #[default]
+    Remove,
+    Insert(T), // Replaces whatever was there, missing fields are the default
+    Update(T),
 }
 
-impl<T: Networked + Copy> Networked for Option<T> {
-    type Optional = Option<T::Optional>;
+impl<T> Networked for Option<T>
+where
+    T: Networked,
+    T::Optional: Default,
+{
+    type Optional = OptionOptional<T::Optional>;
 
     fn from_optional(optional: Self::Optional) -> Self {
-        optional.map(T::from_optional)
+        match optional {
+            OptionOptional::Remove => None,
+            OptionOptional::Insert(optional) | OptionOptional::Update(optional) => Some(T::from_optional(optional)),
+        }
     }
 
     fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
         if let Some(optional) = optional {
             match (self.as_mut(), optional) {
-                (Some(this), Some(other)) => this.update_from_optional(Some(other)),
-                (Some(_), None) => *self = None,
-                (None, Some(other)) => *self = Some(T::from_optional(other)),
-                (None, None) => {},
+                (Some(this), OptionOptional::Update(other)) => this.update_from_optional(Some(other)),
+                (_, optional) => *self = Self::from_optional(optional),
             }
         }
     }
 
     fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
         match (self, other) {
-            (Some(this), Some(other)) => {
-                let diff = this.differences_with(other);
-
-                // If there is a difference return it, otherwise return None
-                if let Some(diff) = diff {
-                    Some(Some(diff))
-                } else {
-                    None
-                }
-            },
-            (None, Some(other)) => Some(other.into_optional()),
-            (Some(_), None) => Some(None),
+            (Some(this), Some(other)) => this.differences_with(other).map(OptionOptional::Update),
+            (None, Some(_)) => other.into_optional(),
+            (Some(_), None) => Some(OptionOptional::Remove),
             (None, None) => None,
         }
     }
 
+    // Also used to replace an existing value, so None is sent as a removal
     fn into_optional(&self) -> Option<Self::Optional> {
         match self {
-            Some(this) => Some(this.into_optional()),
-            None => None,
+            Some(this) => Some(OptionOptional::Insert(this.into_optional().unwrap_or_default())),
+            None => Some(OptionOptional::Remove),
+        }
+    }
+
+    fn checksum(&self) -> u64 {
+        match self {
+            Some(this) => checksum_bytes(checksum_bytes(CHECKSUM_SEED, &[1]), &this.checksum().to_le_bytes()),
+            None => checksum_bytes(CHECKSUM_SEED, &[0]),
+        }
+    }
+
+    // A value being added or removed is always visible, though an added value may be sent with nothing in it.
+    // A value being changed is only sent if something in it is visible
+    fn privatised(optional: Self::Optional, viewer: usize, owned: bool) -> Option<Self::Optional> {
+        match optional {
+            OptionOptional::Remove => Some(OptionOptional::Remove),
+            OptionOptional::Insert(optional) => Some(OptionOptional::Insert(T::privatised(optional, viewer, owned).unwrap_or_default())),
+            OptionOptional::Update(optional) => T::privatised(optional, viewer, owned).map(OptionOptional::Update),
         }
     }
 }
 
+impl<T: Networked> Networked for Box<T> {
+    type Optional = T::Optional;
+
+    fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+        (**self).update_from_optional(optional);
+    }
+
+    fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+        (**self).differences_with(other)
+    }
+
+    fn into_optional(&self) -> Option<Self::Optional> {
+        (**self).into_optional()
+    }
+
+    fn from_optional(optional: Self::Optional) -> Self {
+        Box::new(T::from_optional(optional))
+    }
+
+    fn checksum(&self) -> u64 {
+        (**self).checksum()
+    }
+
+    fn privatised(optional: Self::Optional, viewer: usize, owned: bool) -> Option<Self::Optional> {
+        T::privatised(optional, viewer, owned)
+    }
+}
+
+impl Networked for String {
+    type Optional = String;
+
+    fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+        if let Some(optional) = optional {
+            *self = optional;
+        }
+    }
+
+    fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+        if self != other {
+            Some(other.clone())
+        } else {
+            None
+        }
+    }
+
+    fn into_optional(&self) -> Option<Self::Optional> {
+        Some(self.clone())
+    }
+
+    fn from_optional(optional: Self::Optional) -> Self {
+        optional
+    }
+
+    fn checksum(&self) -> u64 {
+        checksum_bytes(CHECKSUM_SEED, self.as_bytes())
+    }
+}
+
+// The changes to a Vec, these are applied in order: truncate, update then push
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VecOptional<T> {
+    pub truncate: Option<usize>, // The new length if the vec has shrunk
+    pub updates: Vec<(usize, T)>, // Changes to existing elements by index
+    pub push: Vec<Option<T>>, // New elements added to the end, None is the default element
+}
+
+impl<T> Default for VecOptional<T> {
+    fn default() -> Self {
+        Self {
+            truncate: None,
+            updates: Vec::new(),
+            push: Vec::new(),
+        }
+    }
+}
+
+impl<T> VecOptional<T> {
+    fn is_empty(&self) -> bool {
+        self.truncate.is_none() && self.updates.is_empty() && self.push.is_empty()
+    }
+}
+
+impl<T: Networked + Default> Networked for Vec<T> {
+    type Optional = VecOptional<T::Optional>;
+
+    fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+        if let Some(optional) = optional {
+            if let Some(length) = optional.truncate {
+                self.truncate(length);
+            }
+
+            for (index, update) in optional.updates {
+                if let Some(this) = self.get_mut(index) {
+                    this.update_from_optional(Some(update));
+                }
+            }
+
+            self.extend(optional.push.into_iter().map(|push| push.map(T::from_optional).unwrap_or_default()));
+        }
+    }
+
+    fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+        let optional = VecOptional {
+            truncate: (other.len() < self.len()).then_some(other.len()),
+            updates: self.iter()
+                .zip(other.iter())
+                .enumerate()
+                .filter_map(|(index, (x, y))| x.differences_with(y).map(|diff| (index, diff)))
+                .collect(),
+            push: other.iter().skip(self.len()).map(T::into_optional).collect(),
+        };
+
+        (!optional.is_empty()).then_some(optional)
+    }
+
+    // Truncating first means this can also be used to replace an existing vec
+    fn into_optional(&self) -> Option<Self::Optional> {
+        Some(VecOptional {
+            truncate: Some(0),
+            updates: Vec::new(),
+            push: self.iter().map(T::into_optional).collect(),
+        })
+    }
+
+    fn from_optional(optional: Self::Optional) -> Self {
+        let mut this = Vec::new();
+        this.update_from_optional(Some(optional));
+        this
+    }
+
+    fn checksum(&self) -> u64 {
+        let checksum = checksum_bytes(CHECKSUM_SEED, &(self.len() as u64).to_le_bytes());
+        self.iter().fold(checksum, |checksum, this| checksum_bytes(checksum, &this.checksum().to_le_bytes()))
+    }
+
+    // A pushed element with nothing visible is still sent as the default so the length stays the same
+    fn privatised(optional: Self::Optional, viewer: usize, owned: bool) -> Option<Self::Optional> {
+        let optional = VecOptional {
+            truncate: optional.truncate,
+            updates: optional.updates
+                .into_iter()
+                .filter_map(|(index, update)| T::privatised(update, viewer, owned).map(|update| (index, update)))
+                .collect(),
+            push: optional.push
+                .into_iter()
+                .map(|push| push.and_then(|push| T::privatised(push, viewer, owned)))
+                .collect(),
+        };
+
+        (!optional.is_empty()).then_some(optional)
+    }
+}
+
+// The changes to a map, these are applied in order: remove, insert then change
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapOptional<K, T> {
+    pub removed: Vec<K>,
+    pub inserted: Vec<(K, Option<T>)>, // None is the default value
+    pub changed: Vec<(K, T)>,
+}
+
+impl<K, T> Default for MapOptional<K, T> {
+    fn default() -> Self {
+        Self {
+            removed: Vec::new(),
+            inserted: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+}
+
+impl<K, T> MapOptional<K, T> {
+    fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.inserted.is_empty() && self.changed.is_empty()
+    }
+}
+
+macro_rules! impl_networked_map {
+    ($map:ident<K: $($bound:path),+>) => {
+        impl<K, V> Networked for $map<K, V>
+        where
+            K: Serialize + DeserializeOwned + Clone $(+ $bound)+,
+            V: Networked + Default,
+        {
+            type Optional = MapOptional<K, V::Optional>;
+
+            fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+                if let Some(optional) = optional {
+                    for key in optional.removed {
+                        self.remove(&key);
+                    }
+
+                    for (key, value) in optional.inserted {
+                        self.insert(key, value.map(V::from_optional).unwrap_or_default());
+                    }
+
+                    for (key, change) in optional.changed {
+                        if let Some(value) = self.get_mut(&key) {
+                            value.update_from_optional(Some(change));
+                        }
+                    }
+                }
+            }
+
+            fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+                let mut optional = MapOptional::default();
+
+                for (key, value) in self {
+                    match other.get(key) {
+                        Some(other) => {
+                            if let Some(diff) = value.differences_with(other) {
+                                optional.changed.push((key.clone(), diff));
+                            }
+                        },
+                        None => optional.removed.push(key.clone()),
+                    }
+                }
+
+                for (key, value) in other {
+                    if !self.contains_key(key) {
+                        optional.inserted.push((key.clone(), value.into_optional()));
+                    }
+                }
+
+                (!optional.is_empty()).then_some(optional)
+            }
+
+            // This is meant to be applied to an empty map
+            fn into_optional(&self) -> Option<Self::Optional> {
+                Some(MapOptional {
+                    removed: Vec::new(),
+                    inserted: self.iter().map(|(key, value)| (key.clone(), value.into_optional())).collect(),
+                    changed: Vec::new(),
+                })
+            }
+
+            fn from_optional(optional: Self::Optional) -> Self {
+                let mut this = Self::new();
+                this.update_from_optional(Some(optional));
+                this
+            }
+
+            // Entries are added together so the checksum doesn't depend on the iteration order
+            fn checksum(&self) -> u64 {
+                let checksum = checksum_bytes(CHECKSUM_SEED, &(self.len() as u64).to_le_bytes());
+                self.iter().fold(checksum, |checksum, (key, value)| {
+                    let entry = checksum_bytes(CHECKSUM_SEED, &bincode::serialize(key).unwrap());
+                    checksum.wrapping_add(checksum_bytes(entry, &value.checksum().to_le_bytes()))
+                })
+            }
+
+            fn privatised(optional: Self::Optional, viewer: usize, owned: bool) -> Option<Self::Optional> {
+                let optional = MapOptional {
+                    removed: optional.removed,
+                    inserted: optional.inserted
+                        .into_iter()
+                        .map(|(key, value)| (key, value.and_then(|value| V::privatised(value, viewer, owned))))
+                        .collect(),
+                    changed: optional.changed
+                        .into_iter()
+                        .filter_map(|(key, change)| V::privatised(change, viewer, owned).map(|change| (key, change)))
+                        .collect(),
+                };
+
+                (!optional.is_empty()).then_some(optional)
+            }
+        }
+    };
+}
+
+impl_networked_map!(HashMap<K: Eq, Hash>);
+impl_networked_map!(BTreeMap<K: Ord>);
+
+// The changes to an enum deriving Networked, where `T` is the enum of each variant's optional fields. These are kept apart
+// so a change to a variant's fields that can't be seen can be dropped, without losing a change of variant
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum EnumOptional<T> {
+    Replace(T), // The variant has changed, missing fields are the default
+    Update(T), // The variant is the same, only the fields that changed are set
+}
+
+// The derive's optional enum defaults to the enum's `#[default]` variant, so this builds the enum's default value
+impl<T: Default> Default for EnumOptional<T> {
+    fn default() -> Self {
+        Self::Replace(T::default())
+    }
+}
+
 macro_rules! impl_networked {
     ($($t:ty),*) => {
         $(
@@ -134,9 +495,207 @@ macro_rules! impl_networked {
                 fn into_optional(&self) -> Option<Self::Optional> {
                     Some(*self)
                 }
+
+                // Bincode has a fixed width and byte order for every primitive (including usize) so this is stable
+                fn checksum(&self) -> u64 {
+                    checksum_bytes(CHECKSUM_SEED, &bincode::serialize(self).unwrap())
+                }
             }
         )*
     };
 }
 
-impl_networked!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool);
\ No newline at end of file
+impl_networked!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool);
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+
+    // Applies the changes from `from` to `to` the same way a client would, including sending them over the wire
+    fn round_trip<T: Networked + Clone + PartialEq + Debug>(from: T, to: T) {
+        let changes = from.differences_with(&to);
+        let changes: Option<T::Optional> = bincode::deserialize(&bincode::serialize(&changes).unwrap()).unwrap();
+
+        let mut applied = from;
+        applied.update_from_optional(changes);
+        assert_eq!(applied, to);
+        assert_eq!(applied.checksum(), to.checksum());
+    }
+
+    #[test]
+    fn vec_unchanged_has_no_changes() {
+        let vec = vec![1u8, 2, 3];
+        assert!(vec.differences_with(&vec.clone()).is_none());
+    }
+
+    #[test]
+    fn vec_truncate_and_update() {
+        round_trip(vec![1u8, 2, 3, 4], vec![1, 5]);
+        round_trip(vec![1u8, 2, 3], Vec::new());
+
+        let changes = vec![1u8, 2, 3, 4].differences_with(&vec![1, 5]).unwrap();
+        assert_eq!(changes.truncate, Some(2));
+        assert_eq!(changes.updates, vec![(1, 5)]);
+        assert!(changes.push.is_empty());
+    }
+
+    #[test]
+    fn vec_push() {
+        round_trip(Vec::new(), vec![1u8, 2]);
+        round_trip(vec![1u8], vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn vec_push_default_elements() {
+        round_trip(vec![Some(1u8)], vec![Some(1), None, None, Some(2)]);
+        round_trip(Vec::new(), vec![0u8, 0]);
+        round_trip(Vec::new(), vec![Vec::<u8>::new(), Vec::new()]);
+    }
+
+    #[test]
+    fn vec_nested() {
+        round_trip(vec![vec![1u8, 2], vec![3]], vec![vec![1], vec![3, 4], vec![]]);
+    }
+
+    // into_optional is also used for snapshots, which replace whatever the client had
+    #[test]
+    fn vec_into_optional_replaces_non_empty_vec() {
+        let mut vec = vec![9u8, 9, 9];
+        vec.update_from_optional(vec![1u8, 2].into_optional());
+        assert_eq!(vec, vec![1, 2]);
+
+        assert_eq!(Vec::<u8>::from_optional(vec![4u8, 5].into_optional().unwrap()), vec![4, 5]);
+    }
+
+    #[test]
+    fn vec_checksum_depends_on_length() {
+        assert_ne!(vec![0u8].checksum(), Vec::<u8>::new().checksum());
+        assert_ne!(vec![1u8, 2].checksum(), vec![2u8, 1].checksum());
+    }
+
+    #[test]
+    fn map_unchanged_has_no_changes() {
+        let map = HashMap::from([(1u8, 2u8)]);
+        assert!(map.differences_with(&map.clone()).is_none());
+    }
+
+    #[test]
+    fn map_remove_insert_and_change() {
+        let from = HashMap::from([("a".to_string(), 1u8), ("b".to_string(), 2), ("c".to_string(), 3)]);
+        let to = HashMap::from([("a".to_string(), 1u8), ("b".to_string(), 5), ("d".to_string(), 4)]);
+        round_trip(from.clone(), to.clone());
+
+        let changes = from.differences_with(&to).unwrap();
+        assert_eq!(changes.removed, vec!["c".to_string()]);
+        assert_eq!(changes.inserted, vec![("d".to_string(), Some(4))]);
+        assert_eq!(changes.changed, vec![("b".to_string(), 5)]);
+    }
+
+    #[test]
+    fn map_nested_values() {
+        let from = BTreeMap::from([(1u8, vec![1u8, 2]), (2, vec![3])]);
+        let to = BTreeMap::from([(1u8, vec![1u8]), (3, Vec::new())]);
+        round_trip(from, to);
+        round_trip(BTreeMap::new(), BTreeMap::from([(1u8, Some(2u8)), (2, None)]));
+    }
+
+    #[test]
+    fn map_into_optional_builds_the_same_map() {
+        let map = BTreeMap::from([(1u8, 2u8), (3, 4)]);
+        assert_eq!(BTreeMap::<u8, u8>::from_optional(map.into_optional().unwrap()), map);
+
+        let mut empty = BTreeMap::new();
+        empty.update_from_optional(map.into_optional());
+        assert_eq!(empty, map);
+    }
+
+    #[test]
+    fn map_checksum_ignores_order() {
+        let mut forwards = HashMap::new();
+        let mut backwards = HashMap::new();
+        for key in 0..100u32 {
+            forwards.insert(key, key * 2);
+            backwards.insert(99 - key, (99 - key) * 2);
+        }
+
+        assert_eq!(forwards.checksum(), backwards.checksum());
+        assert_eq!(forwards.checksum(), forwards.iter().map(|(k, v)| (*k, *v)).collect::<BTreeMap<_, _>>().checksum());
+        assert_ne!(forwards.checksum(), HashMap::<u32, u32>::new().checksum());
+    }
+
+    // Only visible to its owner, so everything privatising does can be seen from whether it is kept
+    #[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+    struct Secret(u8);
+
+    impl Networked for Secret {
+        type Optional = u8;
+
+        fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+            self.0.update_from_optional(optional);
+        }
+
+        fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+            self.0.differences_with(&other.0)
+        }
+
+        fn into_optional(&self) -> Option<Self::Optional> {
+            Some(self.0)
+        }
+
+        fn from_optional(optional: Self::Optional) -> Self {
+            Self(optional)
+        }
+
+        fn checksum(&self) -> u64 {
+            self.0.checksum()
+        }
+
+        fn privatised(optional: Self::Optional, _viewer: usize, owned: bool) -> Option<Self::Optional> {
+            owned.then_some(optional)
+        }
+    }
+
+    type Players = [Option<Secret>; 2];
+
+    fn players_changes(from: Players, to: Players, viewer: usize) -> Option<<Players as Networked>::Optional> {
+        privatised_players::<Option<Secret>, 2>(from.differences_with(&to).unwrap(), viewer)
+    }
+
+    #[test]
+    fn players_are_only_owned_by_the_viewer_at_their_index() {
+        let from = [Some(Secret(1)), Some(Secret(2))];
+        let to = [Some(Secret(3)), Some(Secret(4))];
+
+        assert_eq!(players_changes(from, to, 0), Some([Some(OptionOptional::Update(3)), None]));
+        assert_eq!(players_changes(from, to, 1), Some([None, Some(OptionOptional::Update(4))]));
+    }
+
+    #[test]
+    fn players_collapse_to_none_when_nothing_is_visible() {
+        let from = [Some(Secret(1)), Some(Secret(2))];
+        assert_eq!(players_changes(from, [Some(Secret(1)), Some(Secret(5))], 0), None);
+    }
+
+    #[test]
+    fn removed_players_are_always_visible() {
+        let from = [Some(Secret(1)), Some(Secret(2))];
+        assert_eq!(players_changes(from, [Some(Secret(1)), None], 0), Some([None, Some(OptionOptional::Remove)]));
+    }
+
+    #[test]
+    fn option_keeps_removals_and_visible_changes() {
+        let from = Some(Secret(1));
+        let changed = from.differences_with(&Some(Secret(2))).unwrap();
+        assert_eq!(Option::<Secret>::privatised(changed.clone(), 0, false), None);
+        assert_eq!(Option::<Secret>::privatised(changed, 0, true), Some(OptionOptional::Update(2)));
+        assert_eq!(Option::<Secret>::privatised(from.differences_with(&None).unwrap(), 0, false), Some(OptionOptional::Remove));
+    }
+
+    #[test]
+    fn option_keeps_inserts_with_nothing_visible() {
+        let inserted = None.differences_with(&Some(Secret(2))).unwrap();
+        assert_eq!(Option::<Secret>::privatised(inserted.clone(), 0, false), Some(OptionOptional::Insert(0)));
+        assert_eq!(Option::<Secret>::privatised(inserted, 0, true), Some(OptionOptional::Insert(2)));
+    }
+}
@@ -1,46 +1,203 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Once, Weak}, time::{Duration, SystemTime, UNIX_EPOCH}};
 
 use axum::extract::ws::{Message, WebSocket};
-use futures::{stream::{SplitSink, SplitStream, StreamExt}, SinkExt};
+use futures::{Sink, SinkExt, Stream, StreamExt};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use tokio::{sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, RwLock}, time::timeout};
+use tokio::{sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, RwLock}, task::JoinHandle, time::{interval, interval_at, sleep, timeout_at, Instant}};
 
-use crate::{events::ServerMessage, ClientEvent, Networked, PlayerFields, RoomFields, RoomLogic, ServerEvent};
+use crate::{events::ServerMessage, ClientEvent, JoinRejection, RoomCloseReason, NamePolicy, Networked, PlayerFields, RoomFields, RoomLogic, ServerEvent};
 
-pub type HandleEventFn<T, const MAX_PLAYERS: usize> = fn(&mut ServerRoom<T, MAX_PLAYERS>, usize, &ClientEvent<<T as RoomLogic>::ClientGameEvent>);
-type RoomMap<T, const MAX_PLAYERS: usize> = Arc<RwLock<HashMap<String, ServerRoom<T, MAX_PLAYERS>>>>;
+pub type HandleEventFn<T> = fn(&mut ServerRoom<T>, usize, &ClientEvent<T>);
+type RoomMap<T> = Arc<RwLock<HashMap<String, ServerRoom<T>>>>;
+type WeakRoomMap<T> = Weak<RwLock<HashMap<String, ServerRoom<T>>>>;
 
-pub struct Connection {
+const REAP_INTERVAL: Duration = Duration::from_secs(10); // How often rooms are checked against their idle timeout and lifetime
+const PING_CHANGE_THRESHOLD: u32 = 20; // How many milliseconds a player's ping has to change by before everyone is sent the new one
+
+pub struct Connection<T> {
     pub id: String,
     pub sender: Option<UnboundedSender<Message>>,
+    baseline: T, // The room as this connection currently has it, every update is calculated against this
+    revision: u64, // The revision of the room the baseline is from
+    updates: u64, // The number of updates sent, used to decide when to send a checksum
+    disconnected_at: Option<Instant>,
+    ping_recorded: bool, // Whether the player's ping has been set from a measurement yet, rather than the field's default
+    generation: u64, // Changes every time a socket joins or reconnects as this connection, so an old socket can't affect a new one
 }
 
-pub struct ServerRoom<T, const MAX_PLAYERS: usize> 
-where 
-    T: RoomLogic + RoomFields + Networked + Copy + Serialize + DeserializeOwned + Default + Send + Sync,
-{
+impl<T: Default> Connection<T> {
+    // New connections start from the default room, the same as a freshly created client
+    pub fn new(id: String, sender: UnboundedSender<Message>) -> Self {
+        Self {
+            id,
+            sender: Some(sender),
+            baseline: T::default(),
+            revision: 0,
+            updates: 0,
+            disconnected_at: None,
+            ping_recorded: false,
+            generation: 0,
+        }
+    }
+}
+
+// When the host is handed to the player that has been in the room the longest, the first player to join is always the host
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HostMigration {
+    Never, // Only when someone joins after the host has left
+    OnLeave, // Only once the host has left the room
+    OnDisconnect(Duration), // Once the host has left, or been disconnected for this long. Zero migrates as soon as they disconnect
+}
+
+#[derive(Clone)]
+pub struct RoomOptions {
+    pub checksum_interval: Option<u64>, // Send a checksum with every nth update to each connection, None to never send them
+    pub name_policy: NamePolicy, // Checked against the name each player joins with
+    pub host_migration: HostMigration,
+    pub reconnect_grace: Option<Duration>, // How long a disconnected player keeps their slot before they are removed, None to keep it forever
+    pub empty_room_timeout: Duration, // How long a room is kept once nobody is connected to it, players can't reconnect to a room that is gone
+    pub idle_timeout: Option<Duration>, // Close the room once no player has sent anything for this long, None to never close it
+    pub max_lifetime: Option<Duration>, // Close the room once it has been open this long, None to never close it
+    pub tick_interval: Option<Duration>, // How often `RoomLogic::on_tick` is called, None to never call it. Set per room with `ServerRoom::set_tick_interval`
+}
+
+impl Default for RoomOptions {
+    fn default() -> Self {
+        Self {
+            checksum_interval: Some(10),
+            name_policy: NamePolicy::default(),
+            host_migration: HostMigration::OnDisconnect(Duration::from_secs(10)),
+            reconnect_grace: Some(Duration::from_secs(60)),
+            empty_room_timeout: Duration::from_secs(60), // The same as the reconnect grace, so nobody loses their slot early
+            idle_timeout: Some(Duration::from_secs(30 * 60)),
+            max_lifetime: None,
+            tick_interval: None,
+        }
+    }
+}
+
+// Returned by `ServerRoom::schedule` to cancel the event. The deadline is when the event is due in milliseconds since the
+// unix epoch on the server's clock, put it in the room for clients to show a countdown
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ScheduleHandle {
+    id: u64,
+    pub deadline: u64,
+}
+
+pub struct ServerRoom<T: RoomLogic + RoomFields> {
     pub room: T,
-    previous_room: T,
-    connections: [Option<Connection>; MAX_PLAYERS],
-    handle_event: HandleEventFn<T, MAX_PLAYERS>,
+    options: RoomOptions,
+    revision: u64, // Incremented every time the room changes are sent out
+    connections: Vec<Option<Connection<T>>>, // One for each player slot in the room
+    join_order: Vec<usize>, // The index of every player in the room, oldest first. Reconnecting doesn't change a player's place
+    host_disconnected_at: Option<Instant>, // Set while the host is disconnected
+    banned: HashSet<String>, // The ids of connections that can't join this room again
+    empty_since: Option<Instant>, // Set while nobody is connected
+    created_at: Instant,
+    last_activity: Instant, // When a player last joined, reconnected or sent an event
+    tick_task: Option<JoinHandle<()>>, // Only running while the room has a tick interval
+    scheduled: HashMap<u64, JoinHandle<()>>, // The events waiting to be delivered, by the id in their handle
+    next_schedule_id: u64,
+    next_generation: u64,
+    rooms: WeakRoomMap<T>, // The map the room is in and its code, so tasks started by the room can find it again
+    code: String,
+    handle_event: HandleEventFn<T>,
 }
 
-impl<T, const MAX_PLAYERS: usize> ServerRoom<T, MAX_PLAYERS> 
+impl<T> ServerRoom<T> 
 where 
-    T: RoomLogic + RoomFields + Networked + Copy + Serialize + DeserializeOwned + Default + Send + Sync,
+    T: RoomLogic + RoomFields + Networked + Clone + Serialize + DeserializeOwned + Default + Send + Sync,
 {
-    pub fn new(handle_event: HandleEventFn<T, MAX_PLAYERS>) -> Self {
-        let mut room = T::default();
-        room.set_host(0);
+    pub fn new(handle_event: HandleEventFn<T>, options: RoomOptions) -> Self {
+        let room = T::default();
+        debug_assert_eq!(room.players().len(), T::MAX_PLAYERS, "RoomFields::MAX_PLAYERS must match the number of player slots");
+        // Sized from the slots themselves so a connection index is always a valid player index
+        let connections = (0..room.players().len()).map(|_| None).collect();
 
         Self {
             room,
-            previous_room: room,
-            connections: [const { None }; MAX_PLAYERS],
+            options,
+            revision: 1, // Revision 0 is the default room every connection starts from
+            connections,
+            join_order: Vec::new(),
+            host_disconnected_at: None,
+            banned: HashSet::new(),
+            empty_since: None,
+            created_at: Instant::now(),
+            last_activity: Instant::now(),
+            tick_task: None,
+            scheduled: HashMap::new(),
+            next_schedule_id: 0,
+            next_generation: 0,
+            rooms: Weak::new(),
+            code: String::new(),
             handle_event,
         }
     }
 
+    // Passes the event to `RoomLogic::on_scheduled` once the time has passed, unless it is cancelled or the room closes first.
+    // Only rooms created by `Rooms` can schedule events, since the room has to be found again when it is due
+    pub fn schedule(&mut self, after: Duration, event: T::ServerGameEvent) -> ScheduleHandle
+    where
+        T: 'static,
+    {
+        let id = self.next_schedule_id;
+        self.next_schedule_id += 1;
+
+        let (rooms, code) = (self.rooms.clone(), self.code.clone());
+        let task = tokio::spawn(async move {
+            sleep(after).await;
+            let Some(rooms) = rooms.upgrade() else { return; };
+            let mut rooms = rooms.write().await;
+            let Some(room) = rooms.get_mut(&code) else { return; };
+
+            if room.scheduled.remove(&id).is_some() {
+                T::on_scheduled(room, event);
+            }
+        });
+        self.scheduled.insert(id, task);
+
+        ScheduleHandle { id, deadline: unix_millis(SystemTime::now() + after) }
+    }
+
+    pub fn options(&self) -> &RoomOptions {
+        &self.options
+    }
+
+    // Rooms start with the options `Rooms` was created with, these only change this room. Timers that are already
+    // running, like a disconnected player's grace period, keep the value they started with. The tick interval has to be
+    // changed with `set_tick_interval` instead, since the task has to be restarted
+    pub fn options_mut(&mut self) -> &mut RoomOptions {
+        &mut self.options
+    }
+
+    // Starts calling `RoomLogic::on_tick` at the given interval, replacing any tick already running, or stops it with None.
+    // Like scheduled events, only rooms created by `Rooms` can tick
+    pub fn set_tick_interval(&mut self, tick_interval: Option<Duration>)
+    where
+        T: 'static,
+    {
+        if let Some(tick_task) = self.tick_task.take() {
+            tick_task.abort();
+        }
+
+        self.options.tick_interval = tick_interval;
+        if let Some(tick_interval) = tick_interval {
+            self.tick_task = Some(tokio::spawn(tick_room(self.rooms.clone(), self.code.clone(), tick_interval)));
+        }
+    }
+
+    // Returns false if the event has already been delivered or cancelled
+    pub fn cancel(&mut self, handle: ScheduleHandle) -> bool {
+        match self.scheduled.remove(&handle.id) {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn get_connection_index(&self, id: &str) -> Option<usize> {
         self.connections.iter().position(|connection| {
             if let Some(connection) = connection {
@@ -51,260 +208,781 @@ where
         })
     }
 
-    pub fn handle_event(&mut self, index: usize, event: &ClientEvent<T::ClientGameEvent>) {
+    // Whether the player's connection is still the one that was given this generation when its socket joined or reconnected
+    fn is_current(&self, index: usize, generation: u64) -> bool {
+        self.connections.get(index).is_some_and(|connection| {
+            connection.as_ref().is_some_and(|connection| connection.generation == generation)
+        })
+    }
+
+    fn next_generation(&mut self) -> u64 {
+        self.next_generation += 1;
+        self.next_generation
+    }
+
+    pub fn is_connected(&self, index: usize) -> bool {
+        self.connections.get(index).is_some_and(|connection| {
+            connection.as_ref().is_some_and(|connection| connection.sender.is_some())
+        })
+    }
+
+    pub fn is_host(&self, index: usize) -> bool {
+        self.room.host() as usize == index
+    }
+
+    pub fn is_banned(&self, id: &str) -> bool {
+        self.banned.contains(id)
+    }
+
+    fn has_player(&self, index: usize) -> bool {
+        self.room.players().get(index).is_some_and(Option::is_some)
+    }
+
+    // Removes the player at the given index from the room and closes their connection once the event has been sent to them.
+    // Returns false if there is no player at the index
+    pub fn kick(&mut self, index: usize) -> bool {
+        if !self.has_player(index) {
+            return false;
+        }
+        self.remove_player(index, &ServerEvent::PlayerKicked { index });
+        true
+    }
+
+    // Kicks the player at the given index and stops their connection id from joining again while the room exists.
+    // Returns false if there is no player at the index
+    pub fn ban(&mut self, index: usize) -> bool {
+        if !self.has_player(index) {
+            return false;
+        }
+        if let Some(connection) = &self.connections[index] {
+            self.banned.insert(connection.id.clone());
+        }
+        self.remove_player(index, &ServerEvent::PlayerBanned { index });
+        true
+    }
+
+    // Every player slot goes empty -> joined -> disconnected -> left -> empty, where a disconnected player can reconnect
+    // and a joined one can leave or be kicked straight away. Only the methods below move a slot between these
+
+    // Fills an empty slot, the first player to join the room becomes the host
+    // Returns the generation given to the connection
+    fn join(&mut self, index: usize, mut connection: Connection<T>, player: T::Player) -> u64 {
+        let generation = self.next_generation();
+        connection.generation = generation;
+        self.last_activity = Instant::now();
+        self.connections[index] = Some(connection);
+        self.room.players_mut()[index] = Some(player);
+        let host_missing = !self.join_order.contains(&(self.room.host() as usize));
+        self.join_order.push(index);
+
+        // The first player to join an empty room becomes the host, as does anyone joining a room the host has left
+        // without it migrating, or whose host has been disconnected long enough to migrate while nobody else was there to
+        // take it. The player that has been in the room the longest is picked, which is the joiner if nobody else is
+        // connected. This is `check_host` without sending the joiner anything, so the room they get with `RoomJoined` comes first
+        let next_host = if host_missing || self.host_due() {
+            self.join_order.iter().copied().find(|&index| self.is_connected(index))
+        } else {
+            None
+        };
+        if let Some(next_host) = next_host {
+            self.host_disconnected_at = None;
+            self.room.set_host(next_host as u8);
+        }
+
+        self.update_except_server_event(index, &ServerEvent::PlayerJoined);
+        if let Some(next_host) = next_host.filter(|_| self.join_order.len() > 1) {
+            self.update_except_server_event(index, &ServerEvent::HostChanged { host: next_host });
+        }
+        self.update_empty();
+        generation
+    }
+
+    // Gives a disconnected player a new sender and generation, returns None if the player isn't in the room.
+    // The connection keeps its baseline, `send_rejoined` decides whether the client can still use it
+    fn reconnect(&mut self, index: usize, sender: UnboundedSender<Message>) -> Option<u64> {
+        let generation = self.next_generation();
+        let Some(Some(player)) = self.room.players_mut().get_mut(index) else { return None; };
+        let Some(connection) = &mut self.connections[index] else { return None; };
+        player.set_disconnected(false);
+
+        connection.sender = Some(sender);
+        connection.disconnected_at = None;
+        connection.generation = generation;
+        self.last_activity = Instant::now();
+
+        self.update_except_server_event(index, &ServerEvent::PlayerReconnected);
+        if self.is_host(index) {
+            self.host_disconnected_at = None;
+        }
+
+        // Like `join`, this is `check_host` without sending the player anything, so `RoomJoined` is still the first thing
+        // they get and their connection is still at the revision the client has
+        let next_host = if self.host_due() { self.next_host() } else { None };
+        if let Some(next_host) = next_host {
+            self.host_disconnected_at = None;
+            self.room.set_host(next_host as u8);
+            self.update_except_server_event(index, &ServerEvent::HostChanged { host: next_host });
+        }
+        self.update_empty();
+        Some(generation)
+    }
+
+    // Sends a reconnected player the room with `RoomJoined`. If the client still has the revision its connection was last
+    // sent it carries on from there and only gets what changed while it was away, otherwise it is sent the whole room
+    fn send_rejoined(&mut self, index: usize, revision: u64) {
+        let caught_up = self.connections[index].as_ref().is_some_and(|connection| connection.revision == revision);
+        if caught_up {
+            self.sync(index, &ServerEvent::RoomJoined);
+        } else {
+            self.send_room(index, &ServerEvent::RoomJoined);
+        }
+    }
+
+    // Keeps the player and their connection id so they can reconnect, but stops sending them updates
+    fn disconnect(&mut self, index: usize) {
+        if let Some(connection) = &mut self.connections[index] {
+            connection.sender = None;
+            connection.disconnected_at = Some(Instant::now());
+        }
+        if let Some(Some(player)) = self.room.players_mut().get_mut(index) {
+            if !player.disconnected() {
+                player.set_disconnected(true);
+                self.update_all_server_event(&ServerEvent::PlayerDisconnected);
+            }
+        }
+
+        if self.is_host(index) && self.host_disconnected_at.is_none() {
+            self.host_disconnected_at = Some(Instant::now());
+            self.check_host();
+        }
+        self.update_empty();
+    }
+
+    // Removes a player once they have been disconnected for the whole grace period. The generation is checked as well,
+    // since the player could have reconnected and disconnected again, or the slot been joined by someone else in that time
+    fn expire(&mut self, index: usize, generation: u64, grace: Duration) {
+        let expired = self.is_current(index, generation) && self.connections[index].as_ref().is_some_and(|connection| {
+            connection.disconnected_at.is_some_and(|at| at.elapsed() >= grace)
+        });
+
+        if expired {
+            self.leave(index);
+        }
+    }
+
+    // The handler is called first so the player is still in the room while the game cleans up after them
+    fn leave(&mut self, index: usize) {
+        (self.handle_event)(self, index, &ClientEvent::LeaveRoom);
+        self.remove_player(index, &ServerEvent::PlayerLeft);
+    }
+
+    // Empties the slot, the player is sent the event before their connection is closed
+    fn remove_player(&mut self, index: usize, event: &ServerEvent<T::ServerGameEvent>) {
+        self.room.players_mut()[index] = None;
+        self.join_order.retain(|&player| player != index);
+        self.update_all_server_event(event);
+
+        // Dropping the sender ends the socket's send task, which closes it after sending anything still queued
+        self.connections[index] = None;
+        self.check_host();
+        self.update_empty();
+    }
+
+    // True once nobody is connected, disconnected players don't keep the room open
+    pub fn is_empty(&self) -> bool {
+        !(0..self.connections.len()).any(|index| self.is_connected(index))
+    }
+
+    // How long the room has been empty for, None if someone is connected
+    pub fn empty_for(&self) -> Option<Duration> {
+        self.empty_since.map(|at| at.elapsed())
+    }
+
+    // Lets everyone connected know why the room is closing, then drops every connection so their sockets close.
+    // The room still has to be removed from the map afterwards
+    fn close(&mut self, reason: RoomCloseReason) {
+        self.update_all_server_event(&ServerEvent::RoomClosed { reason });
+        for connection in &mut self.connections {
+            *connection = None;
+        }
+    }
+
+    // The reason the room should be closed, if it has been idle or open for too long
+    fn close_reason(&self) -> Option<RoomCloseReason> {
+        if self.options.max_lifetime.is_some_and(|lifetime| self.created_at.elapsed() >= lifetime) {
+            Some(RoomCloseReason::Expired)
+        } else if self.options.idle_timeout.is_some_and(|timeout| self.last_activity.elapsed() >= timeout) {
+            Some(RoomCloseReason::Idle)
+        } else {
+            None
+        }
+    }
+
+    fn update_empty(&mut self) {
+        self.empty_since = if self.is_empty() {
+            Some(self.empty_since.unwrap_or_else(Instant::now))
+        } else {
+            None
+        };
+    }
+
+    // Makes the player at the given index the host and lets everyone know
+    pub fn change_host(&mut self, index: usize) {
+        self.host_disconnected_at = None;
+        self.room.set_host(index as u8);
+        self.update_all_server_event(&ServerEvent::HostChanged { host: index });
+    }
+
+    // Hands the host to the connected player that has been in the room the longest, if nobody else is connected
+    // the host stays the same until someone reconnects
+    pub fn migrate_host(&mut self) {
+        if let Some(next) = self.next_host() {
+            self.change_host(next);
+        }
+    }
+
+    // The connected player that has been in the room the longest, other than the host
+    fn next_host(&self) -> Option<usize> {
+        self.join_order.iter().copied().find(|&index| !self.is_host(index) && self.is_connected(index))
+    }
+
+    // Whether the room's options say it is time to migrate the host
+    fn host_due(&self) -> bool {
+        let host = self.room.host() as usize;
+        match self.options.host_migration {
+            HostMigration::Never => false,
+            HostMigration::OnLeave => !self.join_order.contains(&host),
+            HostMigration::OnDisconnect(delay) => {
+                !self.join_order.contains(&host) || self.host_disconnected_at.is_some_and(|at| at.elapsed() >= delay)
+            }
+        }
+    }
+
+    // Migrates the host if it is time to
+    fn check_host(&mut self) {
+        if self.host_due() {
+            self.migrate_host();
+        }
+    }
+
+    pub fn handle_event(&mut self, index: usize, event: &ClientEvent<T>) {
+        // Pings are sent automatically, so they don't count as activity
+        if let ClientEvent::Ping { client_time, rtt } = event {
+            self.pong(index, *client_time, *rtt);
+            return;
+        }
+
+        self.last_activity = Instant::now();
+
+        if let ClientEvent::RequestSnapshot = event {
+            self.send_snapshot(index);
+            return;
+        }
+
+        if let ClientEvent::LeaveRoom = event {
+            self.leave(index);
+            return;
+        }
+
         let is_valid = match event {
             ClientEvent::GameEvent(action) => {
                 self.room.validate_event(index, action)
             }
             ClientEvent::LeaveRoom => {
-                true // Always valid
+                false // Handled above
             }
             ClientEvent::JoinRoom { name: _ } => {
                 false // Should never be called here
             }
+            ClientEvent::RequestSnapshot | ClientEvent::Ping { .. } => {
+                false // Handled above
+            }
+            ClientEvent::KickPlayer { index: target } | ClientEvent::BanPlayer { index: target } => {
+                self.is_host(index) && *target != index && self.has_player(*target)
+            }
+            ClientEvent::TransferHost { index: target } => {
+                // A disconnected player couldn't do anything as host, and would only have it migrated away again
+                self.is_host(index) && *target != index && self.is_connected(*target)
+            }
             ClientEvent::Unknown => {
                 false // This should be impossible
             }
         };
 
-        if is_valid {
-            (self.handle_event)(self, index, event);
+        if !is_valid {
+            return;
+        }
+
+        // The handler sees host actions before they are applied, so the target is still in the room
+        (self.handle_event)(self, index, event);
+        match event {
+            ClientEvent::KickPlayer { index: target } => {
+                self.kick(*target);
+            }
+            ClientEvent::BanPlayer { index: target } => {
+                self.ban(*target);
+            }
+            ClientEvent::TransferHost { index: target } => self.change_host(*target),
+            _ => {}
         }
     }
 
-    pub fn update_all_server_event(&mut self, event: &ServerEvent<T::ServerGameEvent>) {
-        let changes = self.room.differences_with(&self.previous_room);
+    // Answers a ping with the server's clock, then records the round trip time the client measured with its last one
+    fn pong(&mut self, index: usize, client_time: f64, rtt: Option<u32>) {
+        let server_time = unix_millis(SystemTime::now());
+        self.update_one_server_event(index, &ServerEvent::Pong { client_time, server_time });
+
+        let Some(rtt) = rtt else { return; };
+        let Some(Some(player)) = self.room.players_mut().get_mut(index) else { return; };
+        let Some(connection) = &mut self.connections[index] else { return; };
+
+        // The first measurement is always written, since the field's default isn't a real ping to compare against
+        let recorded = std::mem::replace(&mut connection.ping_recorded, true);
+        let previous = player.ping();
+        if recorded && previous.is_some_and(|previous| previous.abs_diff(rtt) < PING_CHANGE_THRESHOLD) {
+            return;
+        }
+
+        player.set_ping(rtt);
+        if player.ping().is_some() && (!recorded || player.ping() != previous) {
+            self.update_all_server_event(&ServerEvent::PingChanged { index });
+        }
+    }
+
+    // Sends the player at the given index the changes to what they can see since their connection's last update.
+    // Players that aren't connected are skipped, they catch up when they reconnect
+    fn sync(&mut self, index: usize, event: &ServerEvent<T::ServerGameEvent>) {
+        let Some(connection) = &mut self.connections[index] else { return; };
+        if connection.sender.is_none() {
+            return;
+        }
+
+        let view = self.room.view_for(index);
+        let changes = connection.baseline
+            .differences_with(&view)
+            .and_then(|changes| T::privatised(changes, index, false));
+        connection.baseline.update_from_optional(changes.clone());
+        let base_revision = Some(connection.revision);
+        connection.revision = self.revision;
 
-        for (i, _) in self.connections.iter().enumerate() {
-            self.send_message(i, event, changes);
+        connection.updates += 1;
+        let checksum = self.options.checksum_interval
+            .filter(|interval| *interval != 0 && connection.updates % interval == 0)
+            .map(|_| connection.baseline.checksum());
+
+        self.send_message(index, event, base_revision, changes, checksum);
+    }
+
+    // Used when a client has missed an update and can no longer apply changes
+    pub fn send_snapshot(&mut self, index: usize) {
+        self.send_room(index, &ServerEvent::Resynced);
+    }
+
+    // Sends the player at the given index everything they can see, tagged with the current revision
+    fn send_room(&mut self, index: usize, event: &ServerEvent<T::ServerGameEvent>) {
+        let Some(connection) = &mut self.connections[index] else { return; };
+        if connection.sender.is_none() {
+            return;
         }
 
-        self.previous_room = self.room;
+        let snapshot = self.room
+            .view_for(index)
+            .into_optional()
+            .and_then(|snapshot| T::privatised(snapshot, index, false));
+        connection.baseline = snapshot.clone().map(T::from_optional).unwrap_or_default();
+        connection.revision = self.revision;
+
+        self.send_message(index, event, None, snapshot, None);
+    }
+
+    pub fn update_all_server_event(&mut self, event: &ServerEvent<T::ServerGameEvent>) {
+        self.revision += 1;
+        for i in 0..self.connections.len() {
+            self.sync(i, event);
+        }
     }
 
     pub fn update_all(&mut self, event: &T::ServerGameEvent) {
         self.update_all_server_event(&ServerEvent::GameEvent(event.clone()));
     }
 
-    // Sends the room changes to all clients except the one at the given index
+    // Sends the room changes to all clients except the one at the given index, that client will
+    // receive them with its next update
     pub fn update_except_server_event(&mut self, index: usize, event: &ServerEvent<T::ServerGameEvent>) {
-        let changes = self.room.differences_with(&self.previous_room);
-
-        for (i, _) in self.connections.iter().enumerate() {
+        self.revision += 1;
+        for i in 0..self.connections.len() {
             if i != index {
-                self.send_message(i, event, changes);
+                self.sync(i, event);
             }
         }
-
-        self.previous_room = self.room;
     }
 
     pub fn update_except(&mut self, index: usize, event: &T::ServerGameEvent) {
         self.update_except_server_event(index, &ServerEvent::GameEvent(event.clone()));
     }
 
-    // Sends the room changes to just one client
-    // Should only be used for private events and if just private fields have changed
+    // Sends the room changes to just one client, everyone else will receive them with their next update
     pub fn update_one_server_event(&mut self, index: usize, event: &ServerEvent<T::ServerGameEvent>) {
-        let changes = self.room.differences_with(&self.previous_room);
-        self.send_message(index, event, changes); // To stop desync issues, we should only send the changes to private fields for the player at this index
-        self.previous_room = self.room;
+        self.revision += 1;
+        self.sync(index, event);
     }
 
     pub fn update_one(&mut self, index: usize, event: &T::ServerGameEvent) {
         self.update_one_server_event(index, &ServerEvent::GameEvent(event.clone()));
     }
 
-    pub fn send_message(&self, index: usize, event: &ServerEvent<T::ServerGameEvent>, changes: Option<T::Optional>) {
+    fn send_message(&self, index: usize, event: &ServerEvent<T::ServerGameEvent>, base_revision: Option<u64>, changes: Option<T::Optional>, checksum: Option<u64>) {
         if let Some(connection) = &self.connections[index] {
             if let Some(sender) = &connection.sender {
-
-                // TODO: use the index to call a .privatised() method on the message room optional
-                // which will remove all fields that are marked as private and not owned by current player (set to None)
-                // Ideally this should also set whole things to None if this means there aren't any changes.
-                // For example lets say the server updates a players private field, but it attempts to send the changes to all players
-                // Only the player who owns the private field should receive the changes, the other players should receive None for that
-                // whole array index.
-
                 let message = ServerMessage::<T> {
                     event: event.clone(),
+                    revision: self.revision,
+                    base_revision,
+                    checksum,
                     room: changes,
                 };
 
-                sender.send(Message::Binary(bincode::serialize(&message).unwrap())).unwrap();
+                // The socket may have closed before its connection was marked as disconnected
+                let _ = sender.send(Message::Binary(bincode::serialize(&message).unwrap()));
             }
         }
     }
 }
 
+// Stops the tick task and any scheduled events with the room, whichever way the room was removed
+impl<T: RoomLogic + RoomFields> Drop for ServerRoom<T> {
+    fn drop(&mut self) {
+        if let Some(tick_task) = &self.tick_task {
+            tick_task.abort();
+        }
+        for task in self.scheduled.values() {
+            task.abort();
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RoomJoinQuery {
     pub id: String,
     pub code: String,
+    #[serde(default)]
+    pub revision: u64, // The revision of the room the client already has when reconnecting, 0 for a new client
 }
 
 #[derive(Clone)]
-pub struct Rooms<T, const MAX_PLAYERS: usize> 
+pub struct Rooms<T> 
 where 
-    T: RoomLogic + RoomFields + Networked + Copy + Serialize + DeserializeOwned + Default + Send + Sync,
+    T: RoomLogic + RoomFields + Networked + Clone + Serialize + DeserializeOwned + Default + Send + Sync,
 {
-    rooms: RoomMap<T, MAX_PLAYERS>,
-    handle_event: HandleEventFn<T, MAX_PLAYERS>,
+    rooms: RoomMap<T>,
+    handle_event: HandleEventFn<T>,
+    options: RoomOptions,
+    reaper: Arc<Once>, // Starts the task that closes idle and expired rooms with the first socket, so no runtime is needed before then
 }
 
-impl <T, const MAX_PLAYERS: usize> Rooms<T, MAX_PLAYERS> 
+impl <T> Rooms<T> 
 where 
-    T: RoomLogic + RoomFields + Networked + Copy + Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+    T: RoomLogic + RoomFields + Networked + Clone + Serialize + DeserializeOwned + Default + Send + Sync + 'static,
 {
-    pub fn new(handle_event: HandleEventFn<T, MAX_PLAYERS>) -> Self {
+    pub fn new(handle_event: HandleEventFn<T>) -> Self {
+        Self::with_options(handle_event, RoomOptions::default())
+    }
+
+    // Every new room starts with these options, a room can then change its own with `ServerRoom::options_mut`
+    pub fn with_options(handle_event: HandleEventFn<T>, options: RoomOptions) -> Self {
         Self {
             rooms: Arc::new(RwLock::new(HashMap::new())),
             handle_event,
+            options,
+            reaper: Arc::new(Once::new()),
         }
     }
 
     pub async fn handle_socket(self, socket: WebSocket, query: RoomJoinQuery) {
-        if query.id.len() != 36 || query.code.len() != 6 { return; }
+        let (sender, receiver) = socket.split();
+        self.handle_connection(sender, receiver, query).await;
+    }
 
+    // Takes the two halves of the socket separately so anything that sends and receives messages can stand in for one
+    async fn handle_connection<S, R, E>(self, sender: S, mut receiver: R, query: RoomJoinQuery)
+    where
+        S: Sink<Message> + Unpin + Send + 'static,
+        R: Stream<Item = Result<Message, E>> + Unpin + Send + 'static,
+        E: Send + 'static,
+    {
+        self.reaper.call_once(|| {
+            tokio::spawn(reap_rooms(Arc::downgrade(&self.rooms)));
+        });
+
+        // Messages are sent from the start, so a rejected name reaches the client while it can still send another
         let (tx, rx) = unbounded_channel::<Message>();
-        let (sender, mut receiver) = socket.split();
+        let mut send_task = tokio::spawn(send_task(sender, rx));
+        if query.id.len() != 36 || query.code.len() != 6 {
+            reject::<T>(tx, send_task, JoinRejection::InvalidQuery).await;
+            return;
+        }
+
         println!("{} attemping to connect to {}", query.id, query.code);
 
-        let player_index = {
-            let result = self.handle_connect(&query.code, &query.id, tx, &mut receiver).await;
-            match result {
-                Ok(player_index) => {
-                    let rooms = self.rooms.read().await;
-                    let room = rooms.get(&query.code).expect("Room should of been created in handle_connect");
-                    let room_optional = room.room.into_optional();
-                    room.send_message(player_index, &ServerEvent::RoomJoined, room_optional);
-                    player_index
-                },
-                Err(e) => {
-                    println!("{} failed to connect to {}: {}", query.id, query.code, e);
-                    return;
-                }
+        // The room has already been sent to the client, since it could be closed as soon as the lock is released
+        let (player_index, generation) = match self.handle_connect(&query, tx.clone(), &mut receiver).await {
+            Ok(joined) => joined,
+            Err(reason) => {
+                println!("{} failed to connect to {}: {:?}", query.id, query.code, reason);
+                reject::<T>(tx, send_task, reason).await;
+                return;
             }
         };
+        drop(tx); // The connection has its own sender, so the socket is closed once the connection is removed
         println!("{} connected to {}", query.id, query.code);
     
         let recv_state = self.rooms.clone();
         let recv_query = query.clone();
 
-        let mut send_task = tokio::spawn(send_task(sender, rx));
-        let mut recv_task = tokio::spawn(receive_task(recv_state, recv_query, player_index, receiver));
+        let mut recv_task = tokio::spawn(receive_task(recv_state, recv_query, player_index, generation, receiver));
 
         tokio::select! {
             _ = &mut send_task => recv_task.abort(),
             _ = &mut recv_task => send_task.abort(),
         };
     
-        // Send a disconnect event to the room (if the player hasn't already left)
         let mut rooms = self.rooms.write().await;
-        let room = rooms.get_mut(&query.code).unwrap();
-        if let Some(Some(player)) = room.room.players_mut().get_mut(player_index) {
-            if !player.disconnected() {
-                player.set_disconnected(true);
-                room.update_all_server_event(&ServerEvent::PlayerDisconnected);
+        let Some(room) = rooms.get_mut(&query.code) else { return; };
+
+        // Send a disconnect event to the room, unless the player has already left, been kicked or reconnected on another
+        // socket. In that case the connection no longer belongs to this socket and the slot may belong to someone else
+        if room.is_current(player_index, generation) {
+            room.disconnect(player_index);
+            if room.is_host(player_index) {
+                if let HostMigration::OnDisconnect(delay) = room.options.host_migration {
+                    let code = query.code.clone();
+                    run_after(self.rooms.clone(), delay, move |rooms| {
+                        if let Some(room) = rooms.get_mut(&code) {
+                            room.check_host();
+                        }
+                    });
+                }
+            }
+
+            if let Some(grace) = room.options.reconnect_grace {
+                let code = query.code.clone();
+                run_after(self.rooms.clone(), grace, move |rooms| {
+                    if let Some(room) = rooms.get_mut(&code) {
+                        room.expire(player_index, generation, grace);
+                    }
+                });
             }
         }
         println!("{} left room {}", query.id, query.code);
 
-        // If all players are disconnected, remove the room
-        if room.room.players().iter().all(|player| {
-            if let Some(player) = player {
-                player.disconnected()
-            } else {
-                true
-            }
-        }) {
-            rooms.remove(&query.code);
-            println!("Room {} closed", query.code);
+        // Give everyone a chance to come back before the room is removed, a single player refreshing the page shouldn't close it
+        if room.is_empty() {
+            self.remove_once_empty(query.code.clone(), room.options.empty_room_timeout);
         }
     }
 
-    async fn handle_connect(&self, code: &String, player_id: &String, tx: UnboundedSender<Message>, receiver: &mut SplitStream<WebSocket>) -> Result<usize, String> {
-        if let Some(player_index) = self.handle_reconnect(code, player_id, tx.clone()).await {
-            return Ok(player_index);
+    // Removes the room once it has been empty for the whole timeout, it is kept if anyone reconnects in the meantime
+    fn remove_once_empty(&self, code: String, timeout: Duration) {
+        run_after(self.rooms.clone(), timeout, move |rooms| {
+            if rooms.get(&code).and_then(ServerRoom::empty_for).is_some_and(|empty_for| empty_for >= timeout) {
+                rooms.remove(&code);
+                println!("Room {} closed", code);
+            }
+        });
+    }
+
+    // Starts the room's tick task as well if the options have a tick interval, the task waits for the rooms lock
+    // so the room will have been inserted by the time it first ticks
+    fn new_room(&self, code: &str) -> ServerRoom<T> {
+        let mut room = ServerRoom::new(self.handle_event, self.options.clone());
+        room.rooms = Arc::downgrade(&self.rooms);
+        room.code = code.to_string();
+        room.set_tick_interval(self.options.tick_interval);
+        room
+    }
+
+    // Sends the joined player the room while the lock is still held, then returns their index and connection generation
+    async fn handle_connect<R, E>(&self, query: &RoomJoinQuery, tx: UnboundedSender<Message>, receiver: &mut R) -> Result<(usize, u64), JoinRejection>
+    where
+        R: Stream<Item = Result<Message, E>> + Unpin,
+    {
+        let (code, player_id) = (&query.code, &query.id);
+        if self.rooms.read().await.get(code).is_some_and(|room| room.is_banned(player_id)) {
+            return Err(JoinRejection::Banned);
         }
 
-        // Wait 10 seconds for the player to provide a name and code
-        let name = match timeout(Duration::from_secs(300), self.wait_for_name(receiver)).await {
-            Ok(Ok(data)) => data,
-            Ok(Err(e)) => return Err(e),
-            Err(_) => return Err("Connection timeout: No name and code provided.".to_string()),
-        };
+        if let Some(reconnected) = self.handle_reconnect(code, player_id, query.revision, tx.clone()).await {
+            return Ok(reconnected);
+        }
 
-        // Now that we have the name, we can lock the rooms map
-        let mut rooms = self.rooms.write().await;
-        let room = rooms.entry(code.clone()).or_insert(ServerRoom::new(self.handle_event));
-        let player_index = room.room.players().iter().position(|player| player.is_none());
+        // Wait 5 minutes for the player to provide a name, if it isn't allowed they can keep trying with others until then
+        let deadline = Instant::now() + Duration::from_secs(300);
+        loop {
+            let name = match timeout_at(deadline, self.wait_for_name(receiver)).await {
+                Ok(Some(data)) => data,
+                Ok(None) => return Err(JoinRejection::NoName),
+                Err(_) => return Err(JoinRejection::Timeout),
+            };
 
-        if let Some(player_index) = player_index {
+            // Now that we have the name, we can lock the rooms map
+            let mut rooms = self.rooms.write().await;
             let mut player = T::Player::default();
-            player.set_name(&name);
-            let connection = Connection { id: player_id.clone(), sender: Some(tx) };
-            room.connections[player_index] = Some(connection);
-            room.room.players_mut()[player_index] = Some(player);
+            let name_policy = rooms.get(code).map_or(&self.options.name_policy, |room| &room.options.name_policy);
+            let taken: Vec<&str> = rooms.get(code)
+                .map(|room| room.room.players().iter().flatten().map(|player| player.name()).collect())
+                .unwrap_or_default();
+            if let Err(reason) = name_policy.apply(&mut player, name.as_ref(), &taken) {
+                send_before_join::<T>(&tx, ServerEvent::NameRejected(reason));
+                continue;
+            }
 
-            room.update_except_server_event(player_index, &ServerEvent::PlayerJoined);
-            return Ok(player_index);
-        }
+            let room = rooms.entry(code.clone()).or_insert_with(|| self.new_room(code));
+            if room.is_banned(player_id) {
+                return Err(JoinRejection::Banned); // They were banned while picking a name
+            }
 
-        // If we reach this point, the room is full
-        return Err("Room is full".to_string());
+            let player_index = room.room.players().iter().position(|player| player.is_none());
+
+            if let Some(player_index) = player_index {
+                let generation = room.join(player_index, Connection::new(player_id.clone(), tx), player);
+                room.sync(player_index, &ServerEvent::RoomJoined);
+                return Ok((player_index, generation));
+            }
+
+            // If we reach this point, the room is full
+            return Err(JoinRejection::RoomFull);
+        }
     }
 
-    async fn handle_reconnect(&self, code: &str, player_id: &String, tx: UnboundedSender<Message>) -> Option<usize> {
+    async fn handle_reconnect(&self, code: &str, player_id: &str, revision: u64, tx: UnboundedSender<Message>) -> Option<(usize, u64)> {
         let mut rooms = self.rooms.write().await;
         let room = rooms.get_mut(code)?;
+        if room.is_banned(player_id) {
+            return None;
+        }
     
-        let player_index = room.get_connection_index(&player_id)?;
-        let player = room.room.players_mut().get_mut(player_index)?.as_mut()?;
-        player.set_disconnected(false);
-        room.connections[player_index] = Some(Connection { id: player_id.clone(), sender: Some(tx) });
-    
-        room.update_except_server_event(player_index, &ServerEvent::PlayerReconnected);
-        return Some(player_index);
+        let player_index = room.get_connection_index(player_id)?;
+        let generation = room.reconnect(player_index, tx)?;
+        room.send_rejoined(player_index, revision);
+        Some((player_index, generation))
     }
 
-    async fn wait_for_name(&self, receiver: &mut SplitStream<WebSocket>) -> Result<[u8; 20], String> {
+    async fn wait_for_name<R, E>(&self, receiver: &mut R) -> Option<<T::Player as PlayerFields>::Name>
+    where
+        R: Stream<Item = Result<Message, E>> + Unpin,
+    {
         while let Some(msg) = receiver.next().await {
             let msg = match msg {
                 Ok(msg) => msg,
                 Err(_) => break,
             };
     
-            match msg {
-                Message::Binary(data) => {
-                    let event = bincode::deserialize::<ClientEvent<T::ClientGameEvent>>(data.as_ref()).unwrap_or_default();
-                    if let ClientEvent::JoinRoom { name } = event {
-                        return Ok(name);
-                    }
+            if let Message::Binary(data) = msg {
+                let event = bincode::deserialize::<ClientEvent<T>>(data.as_ref()).unwrap_or_default();
+                if let ClientEvent::JoinRoom { name } = event {
+                    return Some(name);
                 }
-                _ => {}
             }
         }
-        return Err("No name and code provided".into());
+        None
+    }
+}
+
+// Sends an event to a client that hasn't joined the room yet. It still has the default room from revision 0,
+// so the message is a change from revision 0 that leaves it as it is
+fn send_before_join<T: RoomLogic + Networked>(sender: &UnboundedSender<Message>, event: ServerEvent<T::ServerGameEvent>) {
+    let message = ServerMessage::<T> {
+        event,
+        revision: 0,
+        base_revision: Some(0),
+        checksum: None,
+        room: None,
+    };
+
+    let _ = sender.send(Message::Binary(bincode::serialize(&message).unwrap()));
+}
+
+// Tells the client why it couldn't join, then waits for the socket to close once everything queued for it has been sent
+async fn reject<T: RoomLogic + Networked>(tx: UnboundedSender<Message>, send_task: JoinHandle<()>, reason: JoinRejection) {
+    send_before_join::<T>(&tx, ServerEvent::JoinRejected { reason });
+    drop(tx);
+    let _ = send_task.await;
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+// Closes rooms that have been idle or open for too long, until every clone of the Rooms it was started by has been dropped
+async fn reap_rooms<T>(rooms: WeakRoomMap<T>)
+where
+    T: RoomLogic + RoomFields + Networked + Clone + Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+{
+    let mut interval = interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(rooms) = rooms.upgrade() else { break; };
+
+        rooms.write().await.retain(|code, room| {
+            let Some(reason) = room.close_reason() else { return true; };
+            room.close(reason);
+            println!("Room {} closed: {:?}", code, reason);
+            false
+        });
+    }
+}
+
+// Calls `on_tick` with the time since the last tick, the rooms are only held weakly since the task is owned by the room
+async fn tick_room<T>(rooms: WeakRoomMap<T>, code: String, tick_interval: Duration)
+where
+    T: RoomLogic + RoomFields + Networked + Clone + Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+{
+    let mut interval = interval_at(Instant::now() + tick_interval, tick_interval);
+    let mut last_tick = Instant::now();
+    loop {
+        interval.tick().await;
+        let Some(rooms) = rooms.upgrade() else { break; };
+        let mut rooms = rooms.write().await;
+        let Some(room) = rooms.get_mut(&code) else { break; };
+
+        let now = Instant::now();
+        T::on_tick(room, now - last_tick);
+        last_tick = now;
     }
 }
 
-async fn send_task(mut sender: SplitSink<WebSocket, Message>, mut rx: UnboundedReceiver<Message>) {
+// Runs `f` with the rooms once the delay has passed. Anything could have happened to a room in the meantime,
+// so `f` has to check it is still in the state that was waited for
+fn run_after<T, F>(rooms: RoomMap<T>, delay: Duration, f: F)
+where
+    T: RoomLogic + RoomFields + Networked + Clone + Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+    F: FnOnce(&mut HashMap<String, ServerRoom<T>>) + Send + 'static,
+{
+    tokio::spawn(async move {
+        sleep(delay).await;
+        f(&mut *rooms.write().await);
+    });
+}
+
+async fn send_task<S: Sink<Message> + Unpin>(mut sender: S, mut rx: UnboundedReceiver<Message>) {
     while let Some(msg) = rx.recv().await {
         if sender.send(msg).await.is_err() {
             break;
         }
     }
+    let _ = sender.close().await;
 }
 
-async fn receive_task<T, const MAX_PLAYERS: usize>(
-    recv_state: RoomMap<T, MAX_PLAYERS>,
+async fn receive_task<T, R, E>(
+    recv_state: RoomMap<T>,
     recv_query: RoomJoinQuery,
     player_index: usize,
-    mut receiver: SplitStream<WebSocket>,
+    generation: u64,
+    mut receiver: R,
 ) 
 where
-    T: RoomLogic + RoomFields + Networked + Copy + Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+    T: RoomLogic + RoomFields + Networked + Clone + Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+    R: Stream<Item = Result<Message, E>> + Unpin,
 {
     while let Some(msg) = receiver.next().await {
         let msg = match msg {
@@ -312,29 +990,591 @@ where
             Err(_) => break, // Close the connection if receiving fails
         };
 
-        match msg {
-            Message::Binary(data) => {
-                let event = bincode::deserialize::<ClientEvent<T::ClientGameEvent>>(&data).unwrap_or_default();
-                let mut rooms = recv_state.write().await;
-                let Some(room) = rooms.get_mut(&recv_query.code) else {
-                    break;
-                }; // Cool syntax!
-
-                if let ClientEvent::LeaveRoom = event {
-                    room.connections[player_index] = None;
-                    room.handle_event(player_index, &event);
-                    room.update_all_server_event(&ServerEvent::PlayerLeft);
-                    break;
-                }
+        if let Message::Binary(data) = msg {
+            let event = bincode::deserialize::<ClientEvent<T>>(&data).unwrap_or_default();
+            let mut rooms = recv_state.write().await;
+            let Some(room) = rooms.get_mut(&recv_query.code) else {
+                break;
+            }; // Cool syntax!
 
-                // Ensure the player exists before continuing
-                if room.room.players().get(player_index).is_none() {
-                    break;
-                }
+            // Stop once the slot no longer belongs to this socket, for example after being kicked or reconnecting elsewhere
+            if !room.is_current(player_index, generation) {
+                break;
+            }
 
-                room.handle_event(player_index, &event);
+            room.handle_event(player_index, &event);
+            if let ClientEvent::LeaveRoom = event {
+                break;
             }
-            _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checksum_bytes, FixedStr, NameRejection, CHECKSUM_SEED};
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+    struct Player {
+        name: FixedStr<8>,
+        disconnected: bool,
+        ping: u32,
+    }
+
+    // Sent whole whenever anything in it changes, which is all these tests need
+    impl Networked for Player {
+        type Optional = Self;
+
+        fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+            if let Some(optional) = optional {
+                *self = optional;
+            }
+        }
+
+        fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+            (self != other).then_some(*other)
+        }
+
+        fn into_optional(&self) -> Option<Self::Optional> {
+            Some(*self)
+        }
+
+        fn from_optional(optional: Self::Optional) -> Self {
+            optional
+        }
+
+        fn checksum(&self) -> u64 {
+            checksum_bytes(checksum_bytes(self.name.checksum(), &[self.disconnected as u8]), &self.ping.to_le_bytes())
+        }
+    }
+
+    impl PlayerFields for Player {
+        type Name = FixedStr<8>;
+
+        fn name(&self) -> &str {
+            self.name.as_str()
+        }
+
+        fn set_name(&mut self, name: &str) {
+            self.name = FixedStr::new(name);
+        }
+
+        fn disconnected(&self) -> bool {
+            self.disconnected
+        }
+
+        fn set_disconnected(&mut self, disconnected: bool) {
+            self.disconnected = disconnected;
+        }
+
+        fn ping(&self) -> Option<u32> {
+            Some(self.ping)
+        }
+
+        fn set_ping(&mut self, ping: u32) {
+            self.ping = ping;
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+    struct Room {
+        players: [Option<Player>; 2],
+        host: u8,
+        score: u32,
+        score_hidden: bool, // Not networked, hides the score from player 1 in `view_for`
+    }
+
+    type RoomOptional = (
+        Option<<[Option<Player>; 2] as Networked>::Optional>,
+        Option<u8>,
+        Option<u32>,
+    );
+
+    impl Networked for Room {
+        type Optional = RoomOptional;
+
+        fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
+            if let Some((players, host, score)) = optional {
+                self.players.update_from_optional(players);
+                self.host.update_from_optional(host);
+                self.score.update_from_optional(score);
+            }
+        }
+
+        fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
+            let changes = (
+                self.players.differences_with(&other.players),
+                self.host.differences_with(&other.host),
+                self.score.differences_with(&other.score),
+            );
+            (changes != (None, None, None)).then_some(changes)
+        }
+
+        fn into_optional(&self) -> Option<Self::Optional> {
+            Some((self.players.into_optional(), self.host.into_optional(), self.score.into_optional()))
+        }
+
+        fn from_optional(optional: Self::Optional) -> Self {
+            let mut room = Self::default();
+            room.update_from_optional(Some(optional));
+            room
+        }
+
+        fn checksum(&self) -> u64 {
+            let checksum = checksum_bytes(CHECKSUM_SEED, &self.players.checksum().to_le_bytes());
+            let checksum = checksum_bytes(checksum, &self.host.checksum().to_le_bytes());
+            checksum_bytes(checksum, &self.score.checksum().to_le_bytes())
+        }
+    }
+
+    impl RoomFields for Room {
+        type Player = Player;
+        const MAX_PLAYERS: usize = 2;
+
+        fn players(&self) -> &[Option<Self::Player>] {
+            &self.players
+        }
+
+        fn players_mut(&mut self) -> &mut [Option<Self::Player>] {
+            &mut self.players
+        }
+
+        fn host(&self) -> u8 {
+            self.host
+        }
+
+        fn set_host(&mut self, host: u8) {
+            self.host = host;
+        }
+    }
+
+    impl RoomLogic for Room {
+        type ServerGameEvent = ();
+        type ClientGameEvent = ();
+
+        fn validate_event(&self, _player_index: usize, _action: &()) -> bool {
+            true
+        }
+
+        fn view_for(&self, player_index: usize) -> Self {
+            let mut view = self.clone();
+            if player_index == 1 && self.score_hidden {
+                view.score = 0;
+            }
+            view
+        }
+    }
+
+    fn new_room() -> ServerRoom<Room> {
+        ServerRoom::new(|_, _, _| {}, RoomOptions::default())
+    }
+
+    // Joins the slot the same way `Rooms::handle_socket` does, returning what the socket would be sent
+    fn join(room: &mut ServerRoom<Room>, index: usize) -> UnboundedReceiver<Message> {
+        let (tx, rx) = unbounded_channel();
+        room.join(index, Connection::new(format!("player {}", index), tx), Player { name: FixedStr::new("Sam"), ..Player::default() });
+        room.sync(index, &ServerEvent::RoomJoined);
+        rx
+    }
+
+    fn messages(rx: &mut UnboundedReceiver<Message>) -> Vec<ServerMessage<Room>> {
+        let mut messages = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            if let Message::Binary(data) = message {
+                messages.push(bincode::deserialize(&data).unwrap());
+            }
+        }
+        messages
+    }
+
+    // Applies messages the same way the client does, returning the revision the client ends up at
+    fn apply(client: &mut Room, mut revision: u64, messages: Vec<ServerMessage<Room>>) -> u64 {
+        for message in messages {
+            match message.base_revision {
+                None => *client = message.room.map(Room::from_optional).unwrap_or_default(),
+                Some(base_revision) => {
+                    assert_eq!(base_revision, revision, "client missed an update");
+                    client.update_from_optional(message.room);
+                }
+            }
+            revision = message.revision;
+        }
+        revision
+    }
+
+    #[test]
+    fn first_player_becomes_host_and_is_only_sent_the_room() {
+        let mut room = new_room();
+        let mut first = join(&mut room, 1);
+
+        assert!(room.is_host(1));
+        let messages = messages(&mut first);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0].event, ServerEvent::RoomJoined));
+    }
+
+    #[test]
+    fn joining_a_room_the_host_has_left_hands_it_on_before_room_joined() {
+        let mut room = ServerRoom::<Room>::new(|_, _, _| {}, RoomOptions { host_migration: HostMigration::Never, ..RoomOptions::default() });
+        let _first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+        room.leave(0);
+        assert!(room.is_host(0)); // Never migrates on its own
+        messages(&mut second);
+
+        let mut third = join(&mut room, 0);
+        assert!(room.is_host(1));
+
+        let joined = messages(&mut third);
+        assert_eq!(joined.len(), 1);
+        assert!(matches!(joined[0].event, ServerEvent::RoomJoined));
+        assert_eq!(joined[0].room.as_ref().and_then(|(_, host, _)| *host), Some(1));
+
+        let events: Vec<_> = messages(&mut second).into_iter().map(|message| message.event).collect();
+        assert!(matches!(events[..], [ServerEvent::PlayerJoined, ServerEvent::HostChanged { host: 1 }]));
+    }
+
+    #[test]
+    fn joining_a_room_whose_host_is_disconnected_hands_it_on() {
+        let options = RoomOptions { host_migration: HostMigration::OnDisconnect(Duration::ZERO), reconnect_grace: None, ..RoomOptions::default() };
+        let mut room = ServerRoom::<Room>::new(|_, _, _| {}, options);
+        let _first = join(&mut room, 0);
+        room.disconnect(0);
+        assert!(room.is_host(0)); // Nobody else to hand it to
+
+        let mut second = join(&mut room, 1);
+        assert!(room.is_host(1));
+
+        let joined = messages(&mut second);
+        assert_eq!(joined.len(), 1);
+        assert!(matches!(joined[0].event, ServerEvent::RoomJoined));
+        assert_eq!(joined[0].room.as_ref().and_then(|(_, host, _)| *host), Some(1));
+    }
+
+    #[test]
+    fn reconnecting_to_a_room_without_a_connected_host_takes_it_after_room_joined() {
+        let options = RoomOptions { host_migration: HostMigration::OnDisconnect(Duration::ZERO), ..RoomOptions::default() };
+        let mut room = ServerRoom::<Room>::new(|_, _, _| {}, options);
+        let mut first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+        messages(&mut first);
+
+        let mut client = Room::default();
+        let revision = apply(&mut client, 0, messages(&mut second));
+        room.disconnect(1);
+        room.disconnect(0);
+        assert!(room.is_host(0)); // Nobody connected to hand it to
+
+        let (tx, mut rx) = unbounded_channel();
+        assert!(room.reconnect(1, tx).is_some());
+        room.send_rejoined(1, revision);
+        assert!(room.is_host(1));
+
+        // Still caught up from the revision the client had, with the new host in it
+        let messages = messages(&mut rx);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0].event, ServerEvent::RoomJoined));
+        assert_eq!(messages[0].base_revision, Some(revision));
+        apply(&mut client, revision, messages);
+        assert_eq!(client, room.room);
+    }
+
+    #[test]
+    fn reconnecting_with_the_same_revision_only_sends_what_changed() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+
+        let mut client = Room::default();
+        let revision = apply(&mut client, 0, messages(&mut second));
+        assert_eq!(client, room.room);
+
+        room.disconnect(1);
+        room.room.score = 5;
+        room.update_all(&());
+
+        let (tx, mut rx) = unbounded_channel();
+        assert!(room.reconnect(1, tx).is_some());
+        room.send_rejoined(1, revision);
+
+        let messages = messages(&mut rx);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].base_revision, Some(revision));
+        assert_eq!(messages[0].room, Some((None, None, Some(5))));
+
+        apply(&mut client, revision, messages);
+        assert_eq!(client, room.room);
+    }
+
+    #[test]
+    fn reconnecting_with_a_different_revision_is_sent_the_whole_room() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+
+        let mut client = Room::default();
+        let revision = apply(&mut client, 0, messages(&mut second));
+
+        // Lost as the socket closed, so the client is still at the revision before it
+        room.room.score = 3;
+        room.update_all(&());
+        messages(&mut second);
+
+        room.disconnect(1);
+        room.room.score = 5;
+        room.update_all(&());
+
+        let (tx, mut rx) = unbounded_channel();
+        assert!(room.reconnect(1, tx).is_some());
+        room.send_rejoined(1, revision);
+
+        let messages = messages(&mut rx);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0].event, ServerEvent::RoomJoined));
+        assert_eq!(messages[0].base_revision, None);
+
+        // The client keeps its stale room until the snapshot replaces it
+        apply(&mut client, revision, messages);
+        assert_eq!(client, room.room);
+    }
+
+    #[test]
+    fn hidden_values_are_sent_once_revealed() {
+        let mut room = new_room();
+        let mut first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+        let mut first_client = Room::default();
+        let mut second_client = Room::default();
+        let first_revision = apply(&mut first_client, 0, messages(&mut first));
+        let second_revision = apply(&mut second_client, 0, messages(&mut second));
+
+        room.room.score_hidden = true;
+        room.room.score = 5;
+        room.update_all(&());
+        let first_revision = apply(&mut first_client, first_revision, messages(&mut first));
+        let second_revision = apply(&mut second_client, second_revision, messages(&mut second));
+        assert_eq!(first_client.score, 5);
+        assert_eq!(second_client.score, 0);
+
+        room.room.score_hidden = false;
+        room.update_all(&());
+        let first_messages = messages(&mut first);
+        assert_eq!(first_messages[0].room, None); // Nothing changed for the player that could already see it
+        apply(&mut first_client, first_revision, first_messages);
+        apply(&mut second_client, second_revision, messages(&mut second));
+        assert_eq!(first_client.score, 5);
+        assert_eq!(second_client.score, 5);
+    }
+
+    #[test]
+    fn host_actions_from_other_players_are_ignored() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let _second = join(&mut room, 1);
+
+        room.handle_event(1, &ClientEvent::KickPlayer { index: 0 });
+        room.handle_event(1, &ClientEvent::BanPlayer { index: 0 });
+        room.handle_event(1, &ClientEvent::TransferHost { index: 1 });
+        assert!(room.room.players[0].is_some());
+        assert!(!room.is_banned("player 0"));
+        assert!(room.is_host(0));
+    }
+
+    #[test]
+    fn kicked_players_are_told_before_their_connection_is_dropped() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+        messages(&mut second);
+
+        room.handle_event(0, &ClientEvent::KickPlayer { index: 1 });
+        assert!(room.room.players[1].is_none());
+
+        let kicked = messages(&mut second);
+        assert_eq!(kicked.len(), 1);
+        assert!(matches!(kicked[0].event, ServerEvent::PlayerKicked { index: 1 }));
+        assert!(second.is_closed());
+    }
+
+    #[test]
+    fn kicking_or_banning_an_empty_or_missing_slot_does_nothing() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+
+        assert!(!room.kick(1));
+        assert!(!room.ban(1));
+        assert!(!room.kick(5));
+        assert!(!room.ban(5));
+        assert!(room.room.players[0].is_some());
+    }
+
+    #[test]
+    fn banned_players_cant_come_back() {
+        let rooms = Rooms::<Room>::new(|_, _, _| {});
+        let mut room = rooms.new_room("ABCDEF");
+        let _first = join(&mut room, 0);
+        let _second = join(&mut room, 1);
+        room.disconnect(1);
+        room.handle_event(0, &ClientEvent::BanPlayer { index: 1 });
+        assert!(room.is_banned("player 1"));
+        rooms.rooms.try_write().unwrap().insert("ABCDEF".to_string(), room);
+
+        let (tx, _rx) = unbounded_channel();
+        let reconnected = futures::executor::block_on(rooms.handle_reconnect("ABCDEF", "player 1", 0, tx));
+        assert!(reconnected.is_none());
+    }
+
+    // Fails rather than waiting forever if nothing is sent
+    async fn next_message(socket: &mut futures::channel::mpsc::UnboundedReceiver<Message>) -> ServerMessage<Room> {
+        match tokio::time::timeout(Duration::from_secs(1), socket.next()).await {
+            Ok(Some(Message::Binary(data))) => bincode::deserialize(&data).unwrap(),
+            message => panic!("expected a message, got {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_names_are_sent_while_waiting_for_another() {
+        let rooms = Rooms::<Room>::new(|_, _, _| {});
+        let (sender, mut socket) = futures::channel::mpsc::unbounded();
+        let (client, receiver) = futures::channel::mpsc::unbounded::<Result<Message, axum::Error>>();
+        let query = RoomJoinQuery { id: "0".repeat(36), code: "ABCDEF".to_string(), revision: 0 };
+        let connection = tokio::spawn(rooms.handle_connection(sender, receiver, query));
+
+        let join_room = |name: &str| {
+            let event = ClientEvent::<Room>::JoinRoom { name: FixedStr::new(name) };
+            Ok(Message::Binary(bincode::serialize(&event).unwrap()))
+        };
+        client.unbounded_send(join_room("   ")).unwrap();
+        let rejected = next_message(&mut socket).await;
+        assert!(matches!(rejected.event, ServerEvent::NameRejected(NameRejection::TooShort)));
+
+        client.unbounded_send(join_room("Sam")).unwrap();
+        let joined = next_message(&mut socket).await;
+        assert!(matches!(joined.event, ServerEvent::RoomJoined));
+
+        drop(client);
+        connection.await.unwrap();
+    }
+
+    fn generation(room: &ServerRoom<Room>, index: usize) -> u64 {
+        room.connections[index].as_ref().unwrap().generation
+    }
+
+    #[test]
+    fn reconnecting_restarts_the_grace_period() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let _second = join(&mut room, 1);
+        let first_generation = generation(&room, 1);
+        room.disconnect(1);
+
+        let (tx, _rx) = unbounded_channel();
+        let second_generation = room.reconnect(1, tx).unwrap();
+        room.disconnect(1);
+
+        // The first disconnect's grace period has run out, the second's hasn't
+        room.expire(1, first_generation, Duration::ZERO);
+        room.expire(1, second_generation, Duration::from_secs(60));
+        assert!(room.room.players[1].is_some());
+
+        room.expire(1, second_generation, Duration::ZERO);
+        assert!(room.room.players[1].is_none());
+    }
+
+    #[test]
+    fn expiring_doesnt_remove_someone_new_in_the_slot() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let _second = join(&mut room, 1);
+        let old_generation = generation(&room, 1);
+        room.disconnect(1);
+        room.leave(1);
+
+        let _third = join(&mut room, 1);
+        room.disconnect(1);
+        room.expire(1, old_generation, Duration::ZERO);
+        assert!(room.room.players[1].is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn empty_rooms_are_removed_after_the_timeout() {
+        let rooms = Rooms::<Room>::new(|_, _, _| {});
+        let mut room = rooms.new_room("ABCDEF");
+        let _first = join(&mut room, 0);
+        room.disconnect(0);
+        let timeout = room.options.empty_room_timeout;
+        rooms.rooms.write().await.insert("ABCDEF".to_string(), room);
+
+        rooms.remove_once_empty("ABCDEF".to_string(), timeout);
+        sleep(timeout - Duration::from_secs(1)).await;
+        assert!(rooms.rooms.read().await.contains_key("ABCDEF"));
+
+        sleep(Duration::from_secs(2)).await;
+        assert!(!rooms.rooms.read().await.contains_key("ABCDEF"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_and_expired_rooms_are_closed() {
+        let options = RoomOptions { idle_timeout: Some(Duration::from_secs(60)), max_lifetime: Some(Duration::from_secs(30)), ..RoomOptions::default() };
+        let rooms = Rooms::<Room>::with_options(|_, _, _| {}, options);
+        let mut expiring = rooms.new_room("ABCDEF");
+        let mut idle = rooms.new_room("GHIJKL");
+        idle.options_mut().max_lifetime = None;
+        let mut expiring_player = join(&mut expiring, 0);
+        let mut idle_player = join(&mut idle, 0);
+        messages(&mut expiring_player);
+        messages(&mut idle_player);
+        rooms.rooms.write().await.extend([("ABCDEF".to_string(), expiring), ("GHIJKL".to_string(), idle)]);
+        tokio::spawn(reap_rooms(Arc::downgrade(&rooms.rooms)));
+
+        sleep(Duration::from_secs(35)).await;
+        assert!(!rooms.rooms.read().await.contains_key("ABCDEF"));
+        assert!(rooms.rooms.read().await.contains_key("GHIJKL"));
+        let closed = messages(&mut expiring_player);
+        assert!(matches!(closed[..], [ServerMessage { event: ServerEvent::RoomClosed { reason: RoomCloseReason::Expired }, .. }]));
+
+        sleep(Duration::from_secs(30)).await;
+        assert!(!rooms.rooms.read().await.contains_key("GHIJKL"));
+        let closed = messages(&mut idle_player);
+        assert!(matches!(closed[..], [ServerMessage { event: ServerEvent::RoomClosed { reason: RoomCloseReason::Idle }, .. }]));
+    }
+
+    fn ping(room: &mut ServerRoom<Room>, index: usize, rtt: u32) {
+        room.handle_event(index, &ClientEvent::Ping { client_time: 0.0, rtt: Some(rtt) });
+    }
+
+    fn ping_changes(rx: &mut UnboundedReceiver<Message>) -> usize {
+        messages(rx).iter().filter(|message| matches!(message.event, ServerEvent::PingChanged { .. })).count()
+    }
+
+    #[test]
+    fn first_ping_is_always_recorded() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+        messages(&mut second);
+
+        // Below the threshold from the field's default of 0, but still a real measurement
+        ping(&mut room, 0, 5);
+        assert_eq!(room.room.players[0].unwrap().ping, 5);
+        assert_eq!(ping_changes(&mut second), 1);
+    }
+
+    #[test]
+    fn small_ping_changes_are_not_sent() {
+        let mut room = new_room();
+        let _first = join(&mut room, 0);
+        let mut second = join(&mut room, 1);
+        ping(&mut room, 0, 50);
+        messages(&mut second);
+
+        ping(&mut room, 0, 50 + PING_CHANGE_THRESHOLD - 1);
+        assert_eq!(room.room.players[0].unwrap().ping, 50);
+        assert_eq!(ping_changes(&mut second), 0);
+
+        ping(&mut room, 0, 50 + PING_CHANGE_THRESHOLD);
+        assert_eq!(room.room.players[0].unwrap().ping, 50 + PING_CHANGE_THRESHOLD);
+        assert_eq!(ping_changes(&mut second), 1);
+    }
+}
@@ -1,60 +1,181 @@
-use syn::{punctuated::Punctuated, token::Comma, Attribute, DataStruct, Field, Type, TypeArray};
+use quote::format_ident;
+use syn::{parse_quote, Attribute, DataStruct, DeriveInput, Error, Field, Fields, GenericArgument, Generics, Ident, Index, Member, Path, Type, TypeArray, WhereClause};
 
-pub fn assert_is_struct(data: &syn::Data) -> Result<&DataStruct, String> {
-    if let syn::Data::Struct(data) = data {
-        return Ok(data);
+// Collects errors instead of stopping at the first one so they can all be reported together
+#[derive(Default)]
+pub struct Errors(Option<Error>);
+
+impl Errors {
+    pub fn push(&mut self, error: Error) {
+        match &mut self.0 {
+            Some(errors) => errors.combine(error),
+            None => self.0 = Some(error),
+        }
+    }
+
+    pub fn check<T>(&mut self, result: syn::Result<T>) -> Option<T> {
+        result.map_err(|error| self.push(error)).ok()
+    }
+
+    pub fn finish(self) -> syn::Result<()> {
+        match self.0 {
+            Some(errors) => Err(errors),
+            None => Ok(()),
+        }
     }
+}
+
+pub fn assert_is_struct<'a>(input: &'a DeriveInput, message: &str) -> syn::Result<&'a DataStruct> {
+    match &input.data {
+        syn::Data::Struct(data) => Ok(data),
+        syn::Data::Enum(data) => Err(Error::new(data.enum_token.span, message)),
+        syn::Data::Union(data) => Err(Error::new(data.union_token.span, message)),
+    }
+}
+
+// The members of each field, this lets named, tuple and unit structs (and variants) all be accessed with `.member`,
+// and matched or built with the same `Name { member: binding }` syntax
+pub fn members(fields: &Fields) -> Vec<Member> {
+    fields.iter().enumerate().map(|(index, field)| {
+        match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(index)),
+        }
+    }).collect()
+}
 
-    Err("".to_string())
+pub fn bindings(count: usize, prefix: &str) -> Vec<Ident> {
+    (0..count).map(|index| format_ident!("{}_{}", prefix, index)).collect()
 }
 
-pub fn assert_has_named_fields(fields: &syn::Fields) -> Result<&Punctuated<Field, Comma>, String> {
-    if let syn::Fields::Named(fields) = fields {
-        return Ok(&fields.named);
+// Fields marked with `#[networked(skip)]` only exist on the server, everywhere else they are created from Default
+pub fn is_skipped(field: &Field) -> syn::Result<bool> {
+    let mut skipped = false;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("networked")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skipped = true;
+                Ok(())
+            } else {
+                Err(meta.error("Unknown `#[networked]` option, expected `skip`"))
+            }
+        })?;
     }
+    Ok(skipped)
+}
 
-    Err("".to_string())
+// A field that is part of the optional type, tuple fields are renumbered there since skipped fields are left out
+pub struct NetworkedField<'a> {
+    pub member: Member,
+    pub optional_member: Member,
+    pub field: &'a Field,
 }
 
-pub fn assert_type(field: &Field, ty: &str, message: &str) {
+pub fn networked_fields(fields: &Fields) -> syn::Result<Vec<NetworkedField<'_>>> {
+    let mut errors = Errors::default();
+    let fields: Vec<_> = members(fields).into_iter().zip(fields.iter()).filter(|(_, field)| {
+        errors.check(is_skipped(field)) == Some(false)
+    }).enumerate().map(|(index, (member, field))| {
+        let optional_member = match &member {
+            Member::Named(_) => member.clone(),
+            Member::Unnamed(_) => Member::Unnamed(Index::from(index)),
+        };
+        NetworkedField { member, optional_member, field }
+    }).collect();
+
+    errors.finish()?;
+    Ok(fields)
+}
+
+// Only called once `networked_fields` has succeeded, so any invalid `#[networked]` attributes have already been reported
+pub fn skipped_members(fields: &Fields) -> Vec<Member> {
+    members(fields).into_iter().zip(fields.iter()).filter(|(_, field)| is_skipped(field).unwrap_or(false)).map(|(member, _)| member).collect()
+}
+
+// The expansion only refers to paths through `websocket_rooms` so it works without any imports in the user's crate
+pub fn networked_trait() -> Path {
+    parse_quote! { ::websocket_rooms::core::Networked }
+}
+
+pub fn serde_crate() -> Path {
+    parse_quote! { ::websocket_rooms::serde }
+}
+
+// Generic types need every networked field to be networked for the optional type to exist, so a bound is added for each field type,
+// along with the bounds the optional type relies on for missing fields and its Debug derive
+pub fn networked_where_clause<'a>(generics: &Generics, types: impl Iterator<Item = &'a Type>) -> Option<WhereClause> {
+    if generics.params.is_empty() {
+        return generics.where_clause.clone();
+    }
+
+    let mut where_clause = generics.where_clause.clone().unwrap_or_else(|| parse_quote! { where });
+    for ty in types {
+        where_clause.predicates.push(parse_quote! { #ty: ::websocket_rooms::core::Networked });
+        where_clause.predicates.push(parse_quote! { <#ty as ::websocket_rooms::core::Networked>::Optional: ::std::default::Default + ::std::fmt::Debug });
+    }
+    Some(where_clause)
+}
+
+pub fn assert_type(field: &Field, ty: &str, message: &str) -> syn::Result<()> {
     if let syn::Type::Path(path) = &field.ty {
         if path.path.is_ident(ty) {
-            return;
+            return Ok(());
         }
     }
 
-    panic!("{}", message);
+    Err(Error::new_spanned(&field.ty, message))
 }
 
-pub fn as_array(field: &Field) -> Result<&TypeArray, &'static str> {
+// Returns the length of a `FixedStr<N>`, which is either a literal or a const generic parameter
+pub fn as_fixed_str<'a>(field: &'a Field, message: &str) -> syn::Result<&'a GenericArgument> {
+    if let syn::Type::Path(path) = &field.ty {
+        if let Some(segment) = path.path.segments.last() {
+            if segment.ident == "FixedStr" {
+                if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                    if let Some(length) = args.args.first() {
+                        return Ok(length);
+                    }
+                }
+            }
+        }
+    }
+
+    Err(Error::new_spanned(&field.ty, message))
+}
+
+pub fn as_array<'a>(field: &'a Field, message: &str) -> syn::Result<&'a TypeArray> {
     if let syn::Type::Array(array) = &field.ty {
         return Ok(array);
     }
 
-    Err("")
+    Err(Error::new_spanned(&field.ty, message))
+}
+
+// Missing attributes are reported on the struct name and duplicates on every attribute after the first
+pub fn get_field_with_attribute<'a>(name: &Ident, fields: &'a Fields, attribute: &str) -> syn::Result<(Member, &'a Field)> {
+    get_optional_field_with_attribute(fields, attribute)?.ok_or_else(|| {
+        Error::new(name.span(), format!("Missing field annotated with `#[{}]`", attribute))
+    })
 }
 
-pub fn get_field_with_attribute<'a>(fields: &'a Punctuated<Field, Comma>, attribute: &str) -> Result<&'a Field, String> {
-    let fields: Vec<&Field> = fields.iter().filter(|field| {
+pub fn get_optional_field_with_attribute<'a>(fields: &'a Fields, attribute: &str) -> syn::Result<Option<(Member, &'a Field)>> {
+    let mut fields = members(fields).into_iter().zip(fields.iter()).filter(|(_, field)| {
         has_attr(&field.attrs, attribute)
-    }).collect();
+    });
+
+    let Some(field) = fields.next() else { return Ok(None); };
 
-    if fields.len() == 1 {
-        Ok(&fields[0])
-    } else if fields.len() > 1 {
-        Err(format!("Only one field can be annotated with `{}`", attribute))
-    } else {
-        Err(format!("Missing field annotated with `{}`", attribute))
+    let mut errors = Errors::default();
+    for (_, duplicate) in fields {
+        let attr = duplicate.attrs.iter().find(|attr| attr.path().is_ident(attribute));
+        errors.push(Error::new_spanned(attr, format!("Only one field can be annotated with `#[{}]`", attribute)));
     }
-}
 
-pub fn get_fields_with_attribute<'a>(fields: &'a Punctuated<Field, Comma>, attribute: &str) -> Vec<&'a Field> {
-    fields.iter().filter(|field| {
-        has_attr(&field.attrs, attribute)
-    }).collect()
+    errors.finish()?;
+    Ok(Some(field))
 }
 
-fn has_attr(attrs: &[Attribute], name: &str) -> bool {
+pub fn has_attr(attrs: &[Attribute], name: &str) -> bool {
     attrs.iter().any(|attr| {
         attr.path().is_ident(name)
     })
@@ -65,7 +186,7 @@ pub fn get_option_inner_type(ty: &Type) -> Option<&syn::Type> {
         if let Some(segment) = path.path.segments.last() {
             if segment.ident == "Option" {
                 if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
-                    if let syn::GenericArgument::Type(ty) = &args.args[0] {
+                    if let Some(syn::GenericArgument::Type(ty)) = args.args.first() {
                         return Some(ty);
                     }
                 }
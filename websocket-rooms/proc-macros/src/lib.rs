@@ -1,53 +1,72 @@
-use helpers::{as_array, assert_has_named_fields, assert_is_struct, assert_type, get_field_with_attribute, get_fields_with_attribute, get_option_inner_type};
+use helpers::{as_array, as_fixed_str, assert_is_struct, assert_type, get_field_with_attribute, get_option_inner_type, get_optional_field_with_attribute, Errors};
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput, Expr, Lit};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Expr, ExprLit, GenericArgument, Lit};
 
 mod networked;
 mod helpers;
 
-#[proc_macro_derive(PlayerFields, attributes(name, disconnected))]
+#[proc_macro_derive(PlayerFields, attributes(name, disconnected, ping))]
 pub fn derive_player_fields(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    expand_player_fields(&input).unwrap_or_else(|error| error.to_compile_error().into())
+}
+
+fn expand_player_fields(input: &DeriveInput) -> syn::Result<TokenStream> {
     let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let data = assert_is_struct(input, "PlayerFields can only be applied to structs")?;
+    let mut errors = Errors::default();
 
-    let data = assert_is_struct(&input.data).expect("PlayerFields can only be applied to structs");
-    let fields = assert_has_named_fields(&data.fields).expect("PlayerFields can only be applied to structs with named fields");
-
-    let name_field = get_field_with_attribute(&fields, "name").unwrap_or_else(|e| panic!("{}", e));
-    let name_field_name = name_field.ident.as_ref().unwrap();
-    let name_field_array = as_array(name_field).expect("Field annotated with `#[name]` must be a fixed size array");
-    let name_length = if let Expr::Lit(lit) = &name_field_array.len {
-        if let Lit::Int(lit) = &lit.lit {
-            let length = lit.base10_parse::<usize>().unwrap();
-            if length == 0 {
-                panic!("Field annotated with `#[name]` must be a fixed size array with a length greater than 0");
+    let name_field = errors.check(get_field_with_attribute(name, &data.fields, "name").and_then(|(member, field)| {
+        let length = as_fixed_str(field, "Field annotated with `#[name]` must be a `FixedStr<N>`")?;
+        // The length can also be a const generic, so only literal lengths can be checked here
+        if let GenericArgument::Const(Expr::Lit(ExprLit { lit: Lit::Int(lit), .. })) = length {
+            if lit.base10_parse::<usize>()? == 0 {
+                return Err(Error::new_spanned(lit, "Field annotated with `#[name]` must be a `FixedStr<N>` with a length greater than 0"));
             }
-            length
-        } else {
-            panic!("Field annotated with `#[name]` must be a fixed size array with a literal length");
         }
-    } else {
-        panic!("Field annotated with `#[name]` must be a fixed size array with a literal length");
-    };
-    
-    let disconnected_field = get_field_with_attribute(&fields, "disconnected").unwrap_or_else(|e| panic!("{}", e));
-    let disconnected_field_name = disconnected_field.ident.as_ref().unwrap();
-    assert_type(disconnected_field, "bool", "Field annotated with `#[disconnected]` must be of type `bool`");
+        Ok((member, &field.ty))
+    }));
+
+    let disconnected_field = errors.check(get_field_with_attribute(name, &data.fields, "disconnected").and_then(|(member, field)| {
+        assert_type(field, "bool", "Field annotated with `#[disconnected]` must be of type `bool`")?;
+        Ok(member)
+    }));
+
+    // The ping field is optional, without one the trait's defaults are used and latency isn't recorded
+    let ping_field = errors.check(get_optional_field_with_attribute(&data.fields, "ping").and_then(|field| {
+        field.map(|(member, field)| {
+            assert_type(field, "u32", "Field annotated with `#[ping]` must be of type `u32`")?;
+            Ok(member)
+        }).transpose()
+    }));
+
+    errors.finish()?;
+    let (name_field_name, name_type) = name_field.unwrap();
+    let disconnected_field_name = disconnected_field.unwrap();
+    let ping_methods = ping_field.flatten().map(|ping_field_name| quote! {
+        fn ping(&self) -> ::std::option::Option<u32> {
+            ::std::option::Option::Some(self.#ping_field_name)
+        }
+
+        fn set_ping(&mut self, ping: u32) {
+            self.#ping_field_name = ping;
+        }
+    });
 
     // Generate methods to get and set the name and disconnected fields
     let expanded = quote! {
-        impl websocket_rooms::core::PlayerFields for #name {
-            fn name(&self) -> &[u8] {
-                &self.#name_field_name
+        impl #impl_generics ::websocket_rooms::core::PlayerFields for #name #ty_generics #where_clause {
+            type Name = #name_type;
+
+            fn name(&self) -> &str {
+                self.#name_field_name.as_str()
             }
 
-            fn set_name(&mut self, name: &[u8]) {
-                let len = name.len().min(#name_length);
-                self.#name_field_name[..len].copy_from_slice(&name[..len]);
-                if len < #name_length {
-                    self.#name_field_name[len..].fill(0);
-                }
+            fn set_name(&mut self, name: &str) {
+                self.#name_field_name = ::websocket_rooms::core::FixedStr::new(name);
             }
 
             fn disconnected(&self) -> bool {
@@ -57,33 +76,49 @@ pub fn derive_player_fields(input: TokenStream) -> TokenStream {
             fn set_disconnected(&mut self, disconnected: bool) {
                 self.#disconnected_field_name = disconnected;
             }
+
+            #ping_methods
         }
     };
 
-    TokenStream::from(expanded)
+    Ok(TokenStream::from(expanded))
 }
 
 #[proc_macro_derive(RoomFields, attributes(players, host))]
 pub fn derive_room_fields(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    expand_room_fields(&input).unwrap_or_else(|error| error.to_compile_error().into())
+}
+
+fn expand_room_fields(input: &DeriveInput) -> syn::Result<TokenStream> {
     let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
 
-    let data = assert_is_struct(&input.data).expect("RoomFields can only be applied to structs");
-    let fields = assert_has_named_fields(&data.fields).expect("RoomFields can only be applied to structs with named fields");
+    let data = assert_is_struct(input, "RoomFields can only be applied to structs")?;
+    let mut errors = Errors::default();
 
-    let host_field = get_field_with_attribute(&fields, "host").unwrap_or_else(|e| panic!("{}", e));
-    let host_field_name = host_field.ident.as_ref().unwrap();
-    assert_type(host_field, "u8", "Field annotated with `#[host]` must be of type `u8`");
+    let host_field = errors.check(get_field_with_attribute(name, &data.fields, "host").and_then(|(member, field)| {
+        assert_type(field, "u8", "Field annotated with `#[host]` must be of type `u8`")?;
+        Ok(member)
+    }));
 
-    let players_field = get_field_with_attribute(&fields, "players").unwrap_or_else(|e| panic!("{}", e));
-    let players_field_name = players_field.ident.as_ref().unwrap();
-    let players_field_array = as_array(players_field).expect("Field annotated with `#[players]` must be a fixed size array");
-    let player_array_type = get_option_inner_type(&*players_field_array.elem);
+    let players_field = errors.check(get_field_with_attribute(name, &data.fields, "players").and_then(|(member, field)| {
+        let array = as_array(field, "Field annotated with `#[players]` must be a fixed size array")?;
+        let player_type = get_option_inner_type(&array.elem).ok_or_else(|| {
+            Error::new_spanned(&array.elem, "Field annotated with `#[players]` must be an array of `Option<Player>`")
+        })?;
+        Ok((member, player_type, &array.len))
+    }));
+
+    errors.finish()?;
+    let host_field_name = host_field.unwrap();
+    let (players_field_name, player_array_type, max_players) = players_field.unwrap();
 
     // Generate the RoomFields implementation
     let expanded = quote! {
-        impl websocket_rooms::core::RoomFields for #name {
+        impl #impl_generics ::websocket_rooms::core::RoomFields for #name #ty_generics #where_clause {
             type Player = #player_array_type;
+            const MAX_PLAYERS: usize = #max_players;
 
             fn host(&self) -> u8 {
                 self.#host_field_name
@@ -93,107 +128,49 @@ pub fn derive_room_fields(input: TokenStream) -> TokenStream {
                 self.#host_field_name = host;
             }
 
-            fn players(&self) -> &[Option<Self::Player>] {
+            fn players(&self) -> &[::std::option::Option<Self::Player>] {
                 &self.#players_field_name
             }
 
-            fn players_mut(&mut self) -> &mut [Option<Self::Player>] {
+            fn players_mut(&mut self) -> &mut [::std::option::Option<Self::Player>] {
                 &mut self.#players_field_name
             }
         }
     };
 
-    TokenStream::from(expanded)
+    Ok(TokenStream::from(expanded))
 }
 
-// TODO: Add support for non-networked fields maybe using #[serde(skip)], for now all fields are networked and private fields
-#[proc_macro_derive(Networked, attributes(private, id))]
+// Fields marked with `#[networked(skip)]` are left out of the optional type and are never sent to clients
+#[proc_macro_derive(Networked, attributes(private, id, networked))]
 pub fn derive_networked(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let name = &input.ident;
-    let optional_name = syn::Ident::new(&format!("{}Optional", name), name.span());
-
-    let data = assert_is_struct(&input.data).expect("Networked can only be applied to structs");
-    let fields = assert_has_named_fields(&data.fields).expect("Networked can only be applied to structs with named fields");
-
-    let _private_fields = get_fields_with_attribute(&fields, "private");
-
-    let optional_fields = fields.iter().map(|f| {
-        let field_name = &f.ident;
-        let field_type = &f.ty;
-        quote! {
-            pub #field_name: Option<<#field_type as Networked>::Optional>
-        }
-    });
-
-    let update_from_optional_impl = fields.iter().map(|f| {
-        let field_name = &f.ident;
-        quote! {
-            self.#field_name.update_from_optional(optional.#field_name);
-        }
-    });
-
-    let differences_with_impl = fields.iter().map(|f| {
-        let field_name = &f.ident;
-
-        quote! {
-            if let Some(diff) = self.#field_name.differences_with(&other.#field_name) {
-                let optional = optional.get_or_insert_with(Self::Optional::default);
-                optional.#field_name = Some(diff);
-            }
-        }
-    });
-
-    let into_optional_impl = fields.iter().map(|f| {
-        let field_name = &f.ident;
-        quote! {
-            #field_name: self.#field_name.into_optional()
-        }
-    });
-
-    // Removing the unwrap is ideal but not sure how to handle this yet
-    let from_optional_impl = fields.iter().map(|f| {
-        let field_name = &f.ident;
-        let field_type = &f.ty;
-        quote! {
-            #field_name: <#field_type as Networked>::from_optional(optional.#field_name.unwrap_or_default())
-        }
-    });
-
-    let expanded = quote! {
-        #[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
-        pub struct #optional_name {
-            #(#optional_fields,)*
-        }
-
-        impl websocket_rooms::core::Networked for #name {
-            type Optional = #optional_name;
-
-            fn update_from_optional(&mut self, optional: Option<Self::Optional>) {
-                if let Some(optional) = optional {
-                    #(#update_from_optional_impl)*
-                }
-            }
+    expand_networked(&input).unwrap_or_else(|error| error.to_compile_error().into())
+}
 
-            fn differences_with(&self, other: &Self) -> Option<Self::Optional> {
-                let mut optional: Option<Self::Optional> = None;
-                #(#differences_with_impl)*
-                optional
-            }
+fn expand_networked(input: &DeriveInput) -> syn::Result<TokenStream> {
+    match &input.data {
+        Data::Struct(data) => networked::derive_struct(input, data),
+        Data::Enum(data) => networked::derive_enum(input, data),
+        Data::Union(data) => Err(Error::new(data.union_token.span, "Networked can only be applied to structs and enums")),
+    }
+}
 
-            fn into_optional(&self) -> Option<Self::Optional> {
-                Some(Self::Optional {
-                    #(#into_optional_impl,)*
-                })
-            }
+// Both Networked and RoomFields, so the room's player capacity always comes from its `#[players]` array
+#[proc_macro_derive(Room, attributes(players, host, private, id, networked))]
+pub fn derive_room(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_room(&input).unwrap_or_else(|error| error.to_compile_error().into())
+}
 
-            fn from_optional(optional: Self::Optional) -> Self {
-                Self {
-                    #(#from_optional_impl,)*
-                }
-            }
-        }
-    };
+// Both halves are expanded before any errors are returned, so mistakes in either are reported together
+fn expand_room(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let mut errors = Errors::default();
+    let room_fields = errors.check(expand_room_fields(input));
+    let networked = errors.check(expand_networked(input));
+    errors.finish()?;
 
-    TokenStream::from(expanded)
+    let mut expanded = room_fields.unwrap_or_default();
+    expanded.extend(networked);
+    Ok(expanded)
 }
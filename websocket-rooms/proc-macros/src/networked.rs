@@ -13,4 +13,459 @@
  *  we can compare the 2 and only send the fields that have changed, this is less efficient but requires less boilerplate and is easier to implement
  * 
  * 
- */
\ No newline at end of file
+ */
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{DataEnum, DataStruct, DeriveInput, Error, Fields};
+
+use crate::helpers::{as_array, bindings, has_attr, networked_fields, networked_trait, networked_where_clause, serde_crate, skipped_members, Errors};
+
+pub fn derive_struct(input: &DeriveInput, data: &DataStruct) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let optional_name = format_ident!("{}Optional", name);
+    let networked = networked_trait();
+    let serde = serde_crate();
+    let fields = networked_fields(&data.fields)?;
+    let optional_members: Vec<_> = fields.iter().map(|f| &f.optional_member).collect();
+    let skipped_members = skipped_members(&data.fields);
+
+    let generics = &input.generics;
+    let (impl_generics, ty_generics, _) = generics.split_for_impl();
+    let where_clause = networked_where_clause(generics, fields.iter().map(|f| &f.field.ty));
+
+    let optional_fields = fields.iter().map(|f| {
+        let field_name = &f.field.ident;
+        let colon = &f.field.colon_token;
+        let field_type = &f.field.ty;
+        quote! {
+            pub #field_name #colon ::std::option::Option<<#field_type as #networked>::Optional>
+        }
+    });
+
+    let optional_struct = match &data.fields {
+        Fields::Named(_) => quote! { pub struct #optional_name #generics #where_clause { #(#optional_fields,)* } },
+        Fields::Unnamed(_) => quote! { pub struct #optional_name #generics (#(#optional_fields,)*) #where_clause; },
+        Fields::Unit => quote! { pub struct #optional_name #generics #where_clause; },
+    };
+
+    let update_from_optional_impl = fields.iter().map(|f| {
+        let field_name = &f.member;
+        let optional_name = &f.optional_member;
+        let field_type = &f.field.ty;
+        quote! {
+            <#field_type as #networked>::update_from_optional(&mut self.#field_name, optional.#optional_name);
+        }
+    });
+
+    let differences_with_impl = fields.iter().map(|f| {
+        let field_name = &f.member;
+        let optional_name = &f.optional_member;
+        let field_type = &f.field.ty;
+        quote! {
+            if let ::std::option::Option::Some(diff) = <#field_type as #networked>::differences_with(&self.#field_name, &other.#field_name) {
+                let optional = optional.get_or_insert_with(<Self::Optional as ::std::default::Default>::default);
+                optional.#optional_name = ::std::option::Option::Some(diff);
+            }
+        }
+    });
+
+    let into_optional_impl = fields.iter().map(|f| {
+        let field_name = &f.member;
+        let optional_name = &f.optional_member;
+        let field_type = &f.field.ty;
+        quote! {
+            #optional_name: <#field_type as #networked>::into_optional(&self.#field_name)
+        }
+    });
+
+    // Removing the unwrap is ideal but not sure how to handle this yet
+    let from_optional_impl = fields.iter().map(|f| {
+        let field_name = &f.member;
+        let optional_name = &f.optional_member;
+        let field_type = &f.field.ty;
+        quote! {
+            #field_name: <#field_type as #networked>::from_optional(optional.#optional_name.unwrap_or_default())
+        }
+    });
+
+    let checksum_impl = fields.iter().map(|f| {
+        let field_name = &f.member;
+        let field_type = &f.field.ty;
+        quote! {
+            let checksum = ::websocket_rooms::core::checksum_bytes(checksum, &<#field_type as #networked>::checksum(&self.#field_name).to_le_bytes());
+        }
+    });
+
+    // Private fields are only kept for the owner, and each player in the `#[players]` array is owned by the viewer at the same index
+    let mut errors = Errors::default();
+    let privatised_impl: Vec<_> = fields.iter().map(|f| {
+        let optional_name = &f.optional_member;
+        let field_type = &f.field.ty;
+
+        let privatised = if has_attr(&f.field.attrs, "players") {
+            match as_array(f.field, "Field annotated with `#[players]` must be a fixed size array") {
+                Ok(players_array) => {
+                    let player_type = &players_array.elem;
+                    let players_length = &players_array.len;
+                    quote! { ::websocket_rooms::core::privatised_players::<#player_type, { #players_length }>(field, viewer) }
+                }
+                Err(error) => {
+                    errors.push(error);
+                    quote! {}
+                }
+            }
+        } else {
+            quote! { <#field_type as #networked>::privatised(field, viewer, owned) }
+        };
+
+        let privatised = quote! {
+            if let ::std::option::Option::Some(field) = optional.#optional_name {
+                if let ::std::option::Option::Some(field) = #privatised {
+                    privatised.get_or_insert_with(<Self::Optional as ::std::default::Default>::default).#optional_name = ::std::option::Option::Some(field);
+                }
+            }
+        };
+
+        if has_attr(&f.field.attrs, "private") {
+            quote! {
+                if owned {
+                    #privatised
+                }
+            }
+        } else {
+            privatised
+        }
+    }).collect();
+    errors.finish()?;
+
+    // Clone and Default are implemented by hand since deriving them would require them on every generic parameter
+    let expanded = quote! {
+        #[derive(#serde::Serialize, #serde::Deserialize, ::std::fmt::Debug)]
+        #[serde(crate = "::websocket_rooms::serde", bound = "")]
+        #optional_struct
+
+        impl #impl_generics ::std::clone::Clone for #optional_name #ty_generics #where_clause {
+            fn clone(&self) -> Self {
+                Self { #(#optional_members: ::std::clone::Clone::clone(&self.#optional_members),)* }
+            }
+        }
+
+        impl #impl_generics ::std::default::Default for #optional_name #ty_generics #where_clause {
+            fn default() -> Self {
+                Self { #(#optional_members: ::std::option::Option::None,)* }
+            }
+        }
+
+        #[allow(unused_variables, unused_mut)]
+        impl #impl_generics #networked for #name #ty_generics #where_clause {
+            type Optional = #optional_name #ty_generics;
+
+            fn update_from_optional(&mut self, optional: ::std::option::Option<Self::Optional>) {
+                if let ::std::option::Option::Some(optional) = optional {
+                    #(#update_from_optional_impl)*
+                }
+            }
+
+            fn differences_with(&self, other: &Self) -> ::std::option::Option<Self::Optional> {
+                let mut optional: ::std::option::Option<Self::Optional> = ::std::option::Option::None;
+                #(#differences_with_impl)*
+                optional
+            }
+
+            fn into_optional(&self) -> ::std::option::Option<Self::Optional> {
+                ::std::option::Option::Some(Self::Optional {
+                    #(#into_optional_impl,)*
+                })
+            }
+
+            // Skipped fields are never sent so they always start from their default
+            fn from_optional(optional: Self::Optional) -> Self {
+                Self {
+                    #(#from_optional_impl,)*
+                    #(#skipped_members: ::std::default::Default::default(),)*
+                }
+            }
+
+            fn checksum(&self) -> u64 {
+                let checksum = ::websocket_rooms::core::CHECKSUM_SEED;
+                #(#checksum_impl)*
+                checksum
+            }
+
+            fn privatised(optional: Self::Optional, viewer: usize, owned: bool) -> ::std::option::Option<Self::Optional> {
+                let mut privatised: ::std::option::Option<Self::Optional> = ::std::option::Option::None;
+                #(#privatised_impl)*
+                privatised
+            }
+        }
+    };
+
+    Ok(TokenStream::from(expanded))
+}
+
+/*
+ * Enums are sent as either the full new value when the variant has changed, or the differences in its fields when it hasn't.
+ * Both use the same optional enum of each variant's fields, wrapped in an `EnumOptional` saying which one it is. The receiver
+ * could tell them apart by comparing the variant with its own, but privatising can't, and it has to know whether an empty
+ * change to the fields can be dropped.
+ */
+pub fn derive_enum(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let optional_name = format_ident!("{}Optional", name);
+    let networked = networked_trait();
+    let serde = serde_crate();
+
+    let generics = &input.generics;
+    let (impl_generics, ty_generics, _) = generics.split_for_impl();
+    let mut errors = Errors::default();
+    let variants: Vec<_> = data.variants.iter().filter_map(|variant| {
+        errors.check(networked_fields(&variant.fields)).map(|fields| (&variant.ident, fields))
+    }).collect();
+    errors.finish()?;
+    let where_clause = networked_where_clause(generics, variants.iter().flat_map(|(_, fields)| fields.iter().map(|f| &f.field.ty)));
+
+    if data.variants.is_empty() {
+        return Err(Error::new(name.span(), "Networked can't be applied to enums without variants"));
+    }
+
+    // Missing values, like a private field someone can't see, are built from the default optional. That is the variant
+    // `#[derive(Default)]` picks with `#[default]`, or the first one without it, so it matches the client's starting value.
+    // A hand-written Default has to return the same variant
+    let default_index = data.variants.iter().position(|variant| has_attr(&variant.attrs, "default")).unwrap_or(0);
+    let (default_variant_name, default_variant_fields) = &variants[default_index];
+    let default_variant_members = default_variant_fields.iter().map(|f| &f.optional_member);
+
+    let optional_variants = data.variants.iter().zip(variants.iter()).map(|(variant, (variant_name, fields))| {
+        let optional_fields = fields.iter().map(|f| {
+            let field_name = &f.field.ident;
+            let colon = &f.field.colon_token;
+            let field_type = &f.field.ty;
+            quote! { #field_name #colon ::std::option::Option<<#field_type as #networked>::Optional> }
+        });
+
+        match &variant.fields {
+            Fields::Named(_) => quote! { #variant_name { #(#optional_fields,)* } },
+            Fields::Unnamed(_) => quote! { #variant_name(#(#optional_fields,)*) },
+            Fields::Unit => quote! { #variant_name },
+        }
+    });
+
+    let update_from_optional_impl = variants.iter().map(|(variant_name, fields)| {
+        let members = fields.iter().map(|f| &f.member);
+        let optional_members = fields.iter().map(|f| &f.optional_member);
+        let this = bindings(fields.len(), "this");
+        let optional = bindings(fields.len(), "optional");
+        let field_types = fields.iter().map(|f| &f.field.ty);
+
+        quote! {
+            (Self::#variant_name { #(#members: #this,)* .. }, #optional_name::#variant_name { #(#optional_members: #optional,)* }) => {
+                #(<#field_types as #networked>::update_from_optional(#this, #optional);)*
+            }
+        }
+    });
+
+    // Replacing a value with the same variant, like a snapshot does, sets every field but leaves skipped fields as they are
+    let replace_impl = variants.iter().map(|(variant_name, fields)| {
+        let members = fields.iter().map(|f| &f.member);
+        let optional_members = fields.iter().map(|f| &f.optional_member);
+        let this = bindings(fields.len(), "this");
+        let optional = bindings(fields.len(), "optional");
+        let field_types = fields.iter().map(|f| &f.field.ty);
+
+        quote! {
+            (Self::#variant_name { #(#members: #this,)* .. }, #optional_name::#variant_name { #(#optional_members: #optional,)* }) => {
+                #(*#this = <#field_types as #networked>::from_optional(#optional.unwrap_or_default());)*
+            }
+        }
+    });
+
+    let differences_with_impl = variants.iter().map(|(variant_name, fields)| {
+        let members: Vec<_> = fields.iter().map(|f| &f.member).collect();
+        let optional_members = fields.iter().map(|f| &f.optional_member);
+        let this = bindings(fields.len(), "this");
+        let other = bindings(fields.len(), "other");
+        let diff = bindings(fields.len(), "diff");
+        let field_types = fields.iter().map(|f| &f.field.ty);
+
+        quote! {
+            (Self::#variant_name { #(#members: #this,)* .. }, Self::#variant_name { #(#members: #other,)* .. }) => {
+                #(let #diff = <#field_types as #networked>::differences_with(#this, #other);)*
+                if false #(|| #diff.is_some())* {
+                    ::std::option::Option::Some(::websocket_rooms::core::EnumOptional::Update(#optional_name::#variant_name { #(#optional_members: #diff,)* }))
+                } else {
+                    ::std::option::Option::None
+                }
+            }
+        }
+    });
+
+    let into_optional_impl = variants.iter().map(|(variant_name, fields)| {
+        let members = fields.iter().map(|f| &f.member);
+        let optional_members = fields.iter().map(|f| &f.optional_member);
+        let this = bindings(fields.len(), "this");
+        let field_types = fields.iter().map(|f| &f.field.ty);
+
+        quote! {
+            Self::#variant_name { #(#members: #this,)* .. } => {
+                ::std::option::Option::Some(::websocket_rooms::core::EnumOptional::Replace(
+                    #optional_name::#variant_name { #(#optional_members: <#field_types as #networked>::into_optional(#this),)* }
+                ))
+            }
+        }
+    });
+
+    // Like structs, any fields that are missing are created from the default optional and skipped fields from their default
+    let from_optional_impl = data.variants.iter().zip(variants.iter()).map(|(variant, (variant_name, fields))| {
+        let members = fields.iter().map(|f| &f.member);
+        let optional_members = fields.iter().map(|f| &f.optional_member);
+        let skipped_members = skipped_members(&variant.fields);
+        let optional = bindings(fields.len(), "optional");
+        let field_types = fields.iter().map(|f| &f.field.ty);
+
+        quote! {
+            #optional_name::#variant_name { #(#optional_members: #optional,)* } => {
+                Self::#variant_name {
+                    #(#members: <#field_types as #networked>::from_optional(#optional.unwrap_or_default()),)*
+                    #(#skipped_members: ::std::default::Default::default(),)*
+                }
+            }
+        }
+    });
+
+    let clone_impl = variants.iter().map(|(variant_name, fields)| {
+        let optional_members: Vec<_> = fields.iter().map(|f| &f.optional_member).collect();
+        let this = bindings(fields.len(), "this");
+
+        quote! {
+            Self::#variant_name { #(#optional_members: #this,)* } => Self::#variant_name { #(#optional_members: ::std::clone::Clone::clone(#this),)* },
+        }
+    });
+
+    let checksum_impl = variants.iter().enumerate().map(|(index, (variant_name, fields))| {
+        let members = fields.iter().map(|f| &f.member);
+        let this = bindings(fields.len(), "this");
+        let field_types = fields.iter().map(|f| &f.field.ty);
+        let index = index as u64;
+
+        quote! {
+            Self::#variant_name { #(#members: #this,)* .. } => {
+                let mut checksum = ::websocket_rooms::core::checksum_bytes(::websocket_rooms::core::CHECKSUM_SEED, &#index.to_le_bytes());
+                #(checksum = ::websocket_rooms::core::checksum_bytes(checksum, &<#field_types as #networked>::checksum(#this).to_le_bytes());)*
+                checksum
+            }
+        }
+    });
+
+    // Returns the variant's visible fields along with whether there are any
+    let privatised_impl: Vec<_> = variants.iter().map(|(variant_name, fields)| {
+        let optional_members: Vec<_> = fields.iter().map(|f| &f.optional_member).collect();
+        let optional = bindings(fields.len(), "optional");
+        let privatised = fields.iter().zip(optional.iter()).map(|(f, optional)| {
+            let field_type = &f.field.ty;
+            let visible = if has_attr(&f.field.attrs, "private") {
+                quote! { #optional.filter(|_| owned) }
+            } else {
+                quote! { #optional }
+            };
+            quote! { #visible.and_then(|field| <#field_type as #networked>::privatised(field, viewer, owned)) }
+        });
+
+        quote! {
+            #optional_name::#variant_name { #(#optional_members: #optional,)* } => {
+                #(let #optional = #privatised;)*
+                let visible = false #(|| #optional.is_some())*;
+                (#optional_name::#variant_name { #(#optional_members: #optional,)* }, visible)
+            }
+        }
+    }).collect();
+
+    let expanded = quote! {
+        #[derive(#serde::Serialize, #serde::Deserialize, ::std::fmt::Debug)]
+        #[serde(crate = "::websocket_rooms::serde", bound = "")]
+        pub enum #optional_name #generics #where_clause {
+            #(#optional_variants,)*
+        }
+
+        impl #impl_generics ::std::clone::Clone for #optional_name #ty_generics #where_clause {
+            fn clone(&self) -> Self {
+                match self {
+                    #(#clone_impl)*
+                }
+            }
+        }
+
+        impl #impl_generics ::std::default::Default for #optional_name #ty_generics #where_clause {
+            fn default() -> Self {
+                Self::#default_variant_name { #(#default_variant_members: ::std::option::Option::None,)* }
+            }
+        }
+
+        impl #impl_generics #networked for #name #ty_generics #where_clause {
+            type Optional = ::websocket_rooms::core::EnumOptional<#optional_name #ty_generics>;
+
+            #[allow(unreachable_patterns)]
+            fn update_from_optional(&mut self, optional: ::std::option::Option<Self::Optional>) {
+                match optional {
+                    ::std::option::Option::Some(::websocket_rooms::core::EnumOptional::Replace(optional)) => match (self, optional) {
+                        #(#replace_impl)*
+                        (this, optional) => *this = <Self as #networked>::from_optional(::websocket_rooms::core::EnumOptional::Replace(optional)),
+                    },
+                    ::std::option::Option::Some(::websocket_rooms::core::EnumOptional::Update(optional)) => match (self, optional) {
+                        #(#update_from_optional_impl)*
+                        (this, optional) => *this = <Self as #networked>::from_optional(::websocket_rooms::core::EnumOptional::Replace(optional)),
+                    },
+                    ::std::option::Option::None => {}
+                }
+            }
+
+            fn differences_with(&self, other: &Self) -> ::std::option::Option<Self::Optional> {
+                #[allow(unreachable_patterns)]
+                match (self, other) {
+                    #(#differences_with_impl)*
+                    (_, other) => <Self as #networked>::into_optional(other),
+                }
+            }
+
+            fn into_optional(&self) -> ::std::option::Option<Self::Optional> {
+                match self {
+                    #(#into_optional_impl)*
+                }
+            }
+
+            fn from_optional(optional: Self::Optional) -> Self {
+                let (::websocket_rooms::core::EnumOptional::Replace(optional) | ::websocket_rooms::core::EnumOptional::Update(optional)) = optional;
+                match optional {
+                    #(#from_optional_impl)*
+                }
+            }
+
+            fn checksum(&self) -> u64 {
+                match self {
+                    #(#checksum_impl)*
+                }
+            }
+
+            // A change of variant is always kept even if none of its fields are visible, a change to the fields of the
+            // same variant is dropped if none of them are
+            #[allow(unused_variables)]
+            fn privatised(optional: Self::Optional, viewer: usize, owned: bool) -> ::std::option::Option<Self::Optional> {
+                match optional {
+                    ::websocket_rooms::core::EnumOptional::Replace(optional) => {
+                        let (optional, _) = match optional {
+                            #(#privatised_impl)*
+                        };
+                        ::std::option::Option::Some(::websocket_rooms::core::EnumOptional::Replace(optional))
+                    }
+                    ::websocket_rooms::core::EnumOptional::Update(optional) => {
+                        let (optional, visible) = match optional {
+                            #(#privatised_impl)*
+                        };
+                        visible.then_some(::websocket_rooms::core::EnumOptional::Update(optional))
+                    }
+                }
+            }
+        }
+    };
+
+    Ok(TokenStream::from(expanded))
+}
@@ -1,2 +1,3 @@
-pub use core;
+pub use rooms_core as core;
+pub use rooms_core::{bincode, serde};
 pub use proc_macros;
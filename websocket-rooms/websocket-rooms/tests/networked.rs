@@ -0,0 +1,257 @@
+use std::fmt::Debug;
+
+use websocket_rooms::{bincode, core::{EnumOptional, FixedStr, Networked as _, OptionOptional}, proc_macros::{Networked, PlayerFields, Room}, serde::{Deserialize, Serialize}};
+
+#[derive(Networked, PlayerFields, Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "websocket_rooms::serde")]
+struct Player {
+    #[name]
+    name: FixedStr<8>,
+    #[disconnected]
+    disconnected: bool,
+    score: u8,
+    #[private]
+    cards: Vec<u8>,
+    hand: Option<Hand>,
+}
+
+#[derive(Networked, Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(crate = "websocket_rooms::serde")]
+struct Hand {
+    #[private]
+    cards: Vec<u8>,
+}
+
+#[derive(Room, Clone, Default, PartialEq, Debug)]
+struct Room {
+    #[players]
+    players: [Option<Player>; 2],
+    #[host]
+    host: u8,
+}
+
+#[derive(Networked, Clone, Default, PartialEq, Debug)]
+enum Phase {
+    #[default]
+    Lobby,
+    Playing { round: u8, scores: Vec<u8> },
+    Finished(u8),
+}
+
+// Checks every part of a derived impl agrees: the serialized changes from `from` turn it into `to`, with the same
+// checksum, and `to` can be rebuilt from its own optional
+fn round_trip<T: websocket_rooms::core::Networked + Clone + PartialEq + Debug>(from: T, to: T) {
+    let changes = from.differences_with(&to);
+    let changes: Option<T::Optional> = bincode::deserialize(&bincode::serialize(&changes).unwrap()).unwrap();
+
+    let mut applied = from;
+    applied.update_from_optional(changes);
+    assert_eq!(applied, to);
+    assert_eq!(applied.checksum(), to.checksum());
+    assert_eq!(T::from_optional(to.into_optional().unwrap()), to);
+}
+
+fn player(score: u8, cards: &[u8]) -> Option<Player> {
+    Some(Player { name: FixedStr::new("Sam"), disconnected: false, score, cards: cards.to_vec(), hand: None })
+}
+
+fn room(players: [Option<Player>; 2]) -> Room {
+    Room { players, host: 0 }
+}
+
+// The changes between the two rooms that the player at `viewer` is allowed to see
+fn changes_for(from: &Room, to: &Room, viewer: usize) -> Option<RoomOptional> {
+    from.differences_with(to).and_then(|changes| Room::privatised(changes, viewer, false))
+}
+
+fn players(changes: Option<RoomOptional>) -> [Option<OptionOptional<PlayerOptional>>; 2] {
+    changes.expect("room should have changed").players.expect("players should have changed")
+}
+
+// The changes to a player that was added or changed
+fn player_changes(changes: Option<OptionOptional<PlayerOptional>>) -> PlayerOptional {
+    match changes {
+        Some(OptionOptional::Insert(changes) | OptionOptional::Update(changes)) => changes,
+        changes => panic!("player should have been added or changed, got {:?}", changes),
+    }
+}
+
+#[test]
+fn private_fields_are_hidden_from_other_players() {
+    let from = room([None, None]);
+    let to = room([player(1, &[1, 2]), player(2, &[3, 4])]);
+
+    let [own, other] = players(changes_for(&from, &to, 0));
+    let own = player_changes(own);
+    let other = player_changes(other);
+    assert_eq!(own.cards.unwrap().push, vec![Some(1), Some(2)]);
+    assert_eq!(other.score, Some(2));
+    assert!(other.cards.is_none());
+
+    // What the other player ends up with has their cards left at the default
+    let mut seen = from.clone();
+    seen.update_from_optional(changes_for(&from, &to, 0));
+    assert_eq!(seen, room([player(1, &[1, 2]), player(2, &[])]));
+}
+
+#[test]
+fn private_only_changes_collapse_to_none() {
+    let from = room([player(1, &[1]), player(2, &[2])]);
+    let to = room([player(1, &[1]), player(2, &[2, 3])]);
+
+    assert!(changes_for(&from, &to, 0).is_none());
+
+    let to = room([player(5, &[1]), player(2, &[2, 3])]);
+    let [own, other] = players(changes_for(&from, &to, 0));
+    assert_eq!(player_changes(own).score, Some(5));
+    assert!(other.is_none());
+}
+
+#[test]
+fn owner_still_gets_private_changes() {
+    let from = room([player(1, &[1]), player(2, &[2])]);
+    let to = room([player(1, &[1]), player(2, &[2, 3])]);
+
+    let [own, other] = players(changes_for(&from, &to, 1));
+    assert!(own.is_none());
+    assert_eq!(player_changes(other).cards.unwrap().push, vec![Some(3)]);
+
+    let mut seen = from.clone();
+    seen.update_from_optional(changes_for(&from, &to, 1));
+    assert_eq!(seen, to);
+}
+
+#[test]
+fn removals_are_always_visible() {
+    let from = room([player(1, &[1]), player(2, &[2])]);
+    let to = room([player(1, &[1]), None]);
+
+    for viewer in 0..2 {
+        let [own, other] = players(changes_for(&from, &to, viewer));
+        assert!(own.is_none());
+        assert!(matches!(other, Some(OptionOptional::Remove)));
+    }
+}
+
+#[test]
+fn added_values_are_visible_without_their_private_fields() {
+    let from = room([player(1, &[1]), player(2, &[2])]);
+    let mut to = from.clone();
+    to.players[1].as_mut().unwrap().hand = Some(Hand { cards: vec![3, 4] });
+
+    let [own, other] = players(changes_for(&from, &to, 0));
+    assert!(own.is_none());
+    assert!(matches!(player_changes(other).hand, Some(OptionOptional::Insert(HandOptional { cards: None }))));
+
+    let mut seen = from.clone();
+    seen.update_from_optional(changes_for(&from, &to, 0));
+    assert_eq!(seen.players[1].as_ref().unwrap().hand, Some(Hand::default()));
+
+    // Changes inside it are still hidden
+    let mut changed = to.clone();
+    changed.players[1].as_mut().unwrap().hand = Some(Hand { cards: vec![5] });
+    assert!(changes_for(&to, &changed, 0).is_none());
+}
+
+#[test]
+fn enum_variant_change_sends_the_whole_value() {
+    let from = Phase::Lobby;
+    let to = Phase::Playing { round: 1, scores: vec![3, 4] };
+
+    let changes = from.differences_with(&to);
+    assert!(matches!(changes, Some(EnumOptional::Replace(PhaseOptional::Playing { round: Some(1), scores: Some(_) }))));
+    round_trip(from, to);
+    round_trip(Phase::Playing { round: 1, scores: vec![3] }, Phase::Finished(2));
+    round_trip(Phase::Finished(2), Phase::Lobby);
+}
+
+#[test]
+fn enum_same_variant_only_sends_changed_fields() {
+    let from = Phase::Playing { round: 1, scores: vec![3, 4] };
+    let to = Phase::Playing { round: 2, scores: vec![3, 4] };
+
+    let changes = from.differences_with(&to);
+    assert!(matches!(changes, Some(EnumOptional::Update(PhaseOptional::Playing { round: Some(2), scores: None }))));
+    assert!(to.differences_with(&to.clone()).is_none());
+    round_trip(from, to);
+    round_trip(Phase::Finished(1), Phase::Finished(5));
+}
+
+#[derive(Networked, Clone, Default, PartialEq, Debug)]
+enum Seat {
+    #[default]
+    Watching,
+    Playing { score: u8, #[private] cards: Vec<u8> },
+}
+
+#[test]
+fn enum_private_only_changes_collapse_to_none() {
+    let from = Seat::Playing { score: 1, cards: vec![1] };
+    let to = Seat::Playing { score: 1, cards: vec![1, 2] };
+
+    let changes = from.differences_with(&to).unwrap();
+    assert!(Seat::privatised(changes.clone(), 0, false).is_none());
+    assert!(matches!(Seat::privatised(changes, 0, true), Some(EnumOptional::Update(SeatOptional::Playing { score: None, cards: Some(_) }))));
+
+    let changes = from.differences_with(&Seat::Playing { score: 2, cards: vec![1, 2] }).unwrap();
+    assert!(matches!(Seat::privatised(changes, 0, false), Some(EnumOptional::Update(SeatOptional::Playing { score: Some(2), cards: None }))));
+}
+
+#[test]
+fn enum_variant_change_is_kept_without_visible_fields() {
+    let changes = Seat::Watching.differences_with(&Seat::Playing { score: 0, cards: vec![1] }).unwrap();
+    let changes = Seat::privatised(changes, 0, false);
+    assert!(matches!(changes, Some(EnumOptional::Replace(SeatOptional::Playing { cards: None, .. }))));
+
+    let mut seen = Seat::Watching;
+    seen.update_from_optional(changes);
+    assert_eq!(seen, Seat::Playing { score: 0, cards: Vec::new() });
+}
+
+#[derive(Networked, Clone, Default, PartialEq, Debug)]
+enum Role {
+    Guard,
+    #[default]
+    Unassigned,
+    Spy(u8),
+}
+
+#[derive(Networked, Clone, Default, PartialEq, Debug)]
+struct Agent {
+    id: u8,
+    #[private]
+    role: Role,
+}
+
+#[test]
+fn hidden_enums_are_built_as_their_default_variant() {
+    let agent = Agent { id: 1, role: Role::Spy(3) };
+    let snapshot = Agent::privatised(agent.into_optional().unwrap(), 0, false).unwrap();
+    let from_snapshot = Agent::from_optional(snapshot);
+    assert_eq!(from_snapshot, Agent { id: 1, role: Role::Unassigned });
+
+    // The same as a client that started from the default and was only sent the changes it can see
+    let mut joined = Agent::default();
+    joined.update_from_optional(Agent::default().differences_with(&agent).and_then(|changes| Agent::privatised(changes, 0, false)));
+    assert_eq!(joined, from_snapshot);
+}
+
+#[derive(Networked, Clone, Default, PartialEq, Debug)]
+struct Table {
+    round: u8,
+    #[networked(skip)]
+    deck: Vec<u8>,
+}
+
+#[test]
+fn skipped_fields_are_left_untouched() {
+    let server = Table { round: 2, deck: vec![1, 2, 3] };
+    assert!(Table { round: 2, deck: Vec::new() }.differences_with(&server).is_none());
+
+    let mut client = Table { round: 1, deck: vec![9] };
+    client.update_from_optional(Table::default().differences_with(&server));
+    client.update_from_optional(server.into_optional());
+    assert_eq!(client, Table { round: 2, deck: vec![9] });
+
+    assert_eq!(Table::from_optional(server.into_optional().unwrap()), Table { round: 2, deck: Vec::new() });
+}
@@ -0,0 +1,57 @@
+// Only `websocket_rooms` is a dependency here, and the names the expansion uses are taken by local items
+use websocket_rooms::{core::FixedStr, proc_macros::{Networked, PlayerFields, Room}};
+
+#[allow(dead_code)]
+struct Networked;
+
+#[allow(dead_code)]
+trait Serialize {}
+
+#[allow(dead_code)]
+trait Deserialize {}
+
+#[derive(Networked, PlayerFields, Clone, Default, websocket_rooms::serde::Serialize, websocket_rooms::serde::Deserialize)]
+#[serde(crate = "websocket_rooms::serde")]
+struct Player {
+    #[name]
+    name: FixedStr<20>,
+    #[disconnected]
+    disconnected: bool,
+    #[ping]
+    ping: u32,
+    #[private]
+    cards: Vec<u8>,
+}
+
+#[derive(Networked, Clone, Default)]
+enum Phase {
+    #[default]
+    Lobby,
+    Playing(u8, #[networked(skip)] u64),
+}
+
+#[derive(Room, Clone, Default)]
+struct Room {
+    #[players]
+    players: [Option<Player>; 4],
+    #[host]
+    host: u8,
+    phase: Phase,
+    #[networked(skip)]
+    seed: u64,
+}
+
+#[derive(Networked, Clone, Default)]
+struct Wrapper<T>(T);
+
+const _: () = assert!(<Room as websocket_rooms::core::RoomFields>::MAX_PLAYERS == 4);
+
+fn main() {
+    use websocket_rooms::core::Networked as _;
+
+    let room = Room::default();
+    let mut other = room.clone();
+    other.update_from_optional(room.differences_with(&Room::default()));
+    assert_eq!(room.checksum(), other.checksum());
+    let _ = Wrapper(room).into_optional();
+}
@@ -0,0 +1,11 @@
+use websocket_rooms::{core::FixedStr, proc_macros::PlayerFields};
+
+#[derive(PlayerFields)]
+struct Player {
+    #[name]
+    name: FixedStr<20>,
+    #[disconnected]
+    disconnected: u8,
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+use websocket_rooms::{core::FixedStr, proc_macros::{PlayerFields, RoomFields}};
+
+#[derive(PlayerFields, Default)]
+struct Player {
+    #[name]
+    name: FixedStr<20>,
+    #[disconnected]
+    disconnected: bool,
+}
+
+#[derive(RoomFields)]
+struct Room {
+    #[players]
+    players: [Option<Player>; 4],
+}
+
+fn main() {}
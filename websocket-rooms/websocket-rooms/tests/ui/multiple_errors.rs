@@ -0,0 +1,19 @@
+use websocket_rooms::{core::FixedStr, proc_macros::{PlayerFields, RoomFields}};
+
+#[derive(PlayerFields)]
+struct Player {
+    #[name]
+    name: FixedStr<0>,
+    #[disconnected]
+    disconnected: Option<bool>,
+}
+
+#[derive(RoomFields)]
+struct Room {
+    #[players]
+    players: Vec<Option<u8>>,
+    #[host]
+    host: usize,
+}
+
+fn main() {}
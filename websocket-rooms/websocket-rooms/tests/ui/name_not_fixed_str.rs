@@ -0,0 +1,11 @@
+use websocket_rooms::proc_macros::PlayerFields;
+
+#[derive(PlayerFields)]
+struct Player {
+    #[name]
+    name: [u8; 20],
+    #[disconnected]
+    disconnected: bool,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use websocket_rooms::{core::FixedStr, proc_macros::PlayerFields};
+
+#[derive(PlayerFields)]
+struct Player {
+    #[name]
+    name: FixedStr<20>,
+    #[disconnected]
+    disconnected: bool,
+    #[ping]
+    ping: f32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use websocket_rooms::proc_macros::RoomFields;
+
+#[derive(RoomFields)]
+struct Room {
+    #[host]
+    host: u8,
+    #[players]
+    players: [Option<>; 4],
+}
+
+fn main() {}
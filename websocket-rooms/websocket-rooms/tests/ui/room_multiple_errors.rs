@@ -0,0 +1,20 @@
+use websocket_rooms::{core::FixedStr, proc_macros::{PlayerFields, Room}};
+
+#[derive(PlayerFields, Default)]
+struct Player {
+    #[name]
+    name: FixedStr<20>,
+    #[disconnected]
+    disconnected: bool,
+}
+
+// Missing `#[host]` comes from the RoomFields half and the unknown option from the Networked half
+#[derive(Room)]
+struct Room {
+    #[players]
+    players: [Option<Player>; 4],
+    #[networked(hidden)]
+    deck: Vec<u8>,
+}
+
+fn main() {}
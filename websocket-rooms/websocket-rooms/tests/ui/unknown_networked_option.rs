@@ -0,0 +1,11 @@
+use websocket_rooms::proc_macros::Networked;
+
+#[derive(Networked)]
+struct Room {
+    #[networked(skip)]
+    seed: u64,
+    #[networked(hidden)]
+    deck: Vec<u8>,
+}
+
+fn main() {}